@@ -0,0 +1,378 @@
+use rerun::external::glam::{Mat3, Quat, Vec3};
+
+use crate::{
+    bvh::Bvh,
+    mesh::Mesh,
+    metric::nearest,
+    score::{Candidate, Scorer},
+    transform::Rigid,
+};
+
+/// Surface points per mesh used for PCA and ICP
+pub const ALIGN_SAMPLES: usize = 512;
+pub const ICP_ITERATIONS: usize = 30;
+
+/// Eigen decomposition of a symmetric matrix by cyclic Jacobi rotations,
+/// eigenvectors are the columns of the second result
+#[allow(clippy::needless_range_loop)]
+pub fn jacobi_eigen<const N: usize>(mut a: [[f64; N]; N]) -> ([f64; N], [[f64; N]; N]) {
+    let mut v = [[0.0; N]; N];
+    for (i, row) in v.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+
+    for _ in 0..64 {
+        let mut off = 0.0;
+        for p in 0..N {
+            for q in p + 1..N {
+                off += a[p][q] * a[p][q];
+            }
+        }
+        if off < 1e-24 {
+            break;
+        }
+
+        for p in 0..N {
+            for q in p + 1..N {
+                if a[p][q].abs() < 1e-30 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for row in a.iter_mut() {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - s * kq;
+                    row[q] = s * kp + c * kq;
+                }
+                for k in 0..N {
+                    let (pk, qk) = (a[p][k], a[q][k]);
+                    a[p][k] = c * pk - s * qk;
+                    a[q][k] = s * pk + c * qk;
+                }
+                for row in v.iter_mut() {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - s * kq;
+                    row[q] = s * kp + c * kq;
+                }
+            }
+        }
+    }
+
+    let mut values = [0.0; N];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = a[i][i];
+    }
+    (values, v)
+}
+
+pub fn centroid(points: &[Vec3]) -> Vec3 {
+    points.iter().copied().sum::<Vec3>() / points.len().max(1) as f32
+}
+
+/// Principal axes of a point set, sorted by decreasing variance, right handed
+pub fn principal_axes(points: &[Vec3]) -> (Vec3, [Vec3; 3]) {
+    let c = centroid(points);
+    let mut cov = [[0.0f64; 3]; 3];
+    for p in points {
+        let d = (*p - c).as_dvec3().to_array();
+        for i in 0..3 {
+            for j in 0..3 {
+                cov[i][j] += d[i] * d[j];
+            }
+        }
+    }
+
+    let (values, vectors) = jacobi_eigen(cov);
+    let mut order = [0, 1, 2];
+    order.sort_by(|a, b| values[*b].total_cmp(&values[*a]));
+    let axis = |i: usize| {
+        Vec3::new(
+            vectors[0][order[i]] as f32,
+            vectors[1][order[i]] as f32,
+            vectors[2][order[i]] as f32,
+        )
+    };
+
+    let mut axes = [axis(0), axis(1), axis(2)];
+    // eigenvectors have no sign, point each one towards the heavier tail
+    for axis in axes.iter_mut().take(2) {
+        let skew: f32 = points.iter().map(|p| (*p - c).dot(*axis).powi(3)).sum();
+        if skew < 0.0 {
+            *axis = -*axis;
+        }
+    }
+    axes[2] = axes[0].cross(axes[1]);
+    (c, axes)
+}
+
+/// Transform into the canonical frame: centroid at the origin, principal axes along x, y, z
+pub fn pca_frame(points: &[Vec3]) -> Rigid {
+    let (c, axes) = principal_axes(points);
+    let rotation = Quat::from_mat3(&Mat3::from_cols(axes[0], axes[1], axes[2]).transpose());
+    Rigid {
+        rotation,
+        translation: -(rotation * c),
+    }
+}
+
+/// Least squares rigid transform taking `source[i]` onto `target[i]` (Horn's quaternion method)
+pub fn best_rigid(source: &[Vec3], target: &[Vec3]) -> Rigid {
+    let (cs, ct) = (centroid(source), centroid(target));
+    let mut s = [[0.0f64; 3]; 3];
+    for (a, b) in source.iter().zip(target) {
+        let (a, b) = ((*a - cs).as_dvec3(), (*b - ct).as_dvec3());
+        let (a, b) = (a.to_array(), b.to_array());
+        for i in 0..3 {
+            for j in 0..3 {
+                s[i][j] += a[i] * b[j];
+            }
+        }
+    }
+
+    let [[xx, xy, xz], [yx, yy, yz], [zx, zy, zz]] = s;
+    let n = [
+        [xx + yy + zz, yz - zy, zx - xz, xy - yx],
+        [yz - zy, xx - yy - zz, xy + yx, zx + xz],
+        [zx - xz, xy + yx, -xx + yy - zz, yz + zy],
+        [xy - yx, zx + xz, yz + zy, -xx - yy + zz],
+    ];
+    let (values, vectors) = jacobi_eigen(n);
+    let best = (0..4)
+        .max_by(|a, b| values[*a].total_cmp(&values[*b]))
+        .unwrap_or(0);
+    let [w, x, y, z] = [0, 1, 2, 3].map(|i| vectors[i][best] as f32);
+    let rotation = Quat::from_xyzw(x, y, z, w).normalize();
+
+    Rigid {
+        rotation,
+        translation: ct - rotation * cs,
+    }
+}
+
+/// Target side of a registration, the surface when there is one, its points otherwise
+#[derive(Clone, Debug)]
+pub struct Aligner {
+    bvh: Option<Bvh>,
+    points: Vec<Vec3>,
+    frame: Rigid,
+}
+
+impl Aligner {
+    pub fn new(target: &Mesh) -> Self {
+        let points = target.surface_samples(ALIGN_SAMPLES);
+        Self {
+            bvh: Some(Bvh::new(target)).filter(|bvh| !bvh.is_empty()),
+            frame: pca_frame(&points),
+            points,
+        }
+    }
+
+    /// Canonical frame of the target, see [`pca_frame`]
+    pub fn frame(&self) -> Rigid {
+        self.frame
+    }
+
+    fn closest(&self, p: Vec3) -> (Vec3, f32) {
+        match &self.bvh {
+            Some(bvh) => bvh.closest_point(p).unwrap_or((p, 0.0)),
+            None => nearest(p, &self.points).map_or((p, 0.0), |(i, d)| (self.points[i], d)),
+        }
+    }
+
+    /// Iterative closest point from `init`, returns the transform and its mean squared error
+    pub fn icp(&self, source: &[Vec3], init: Rigid, iterations: usize) -> (Rigid, f32) {
+        let mut current = init;
+        let mut last = f32::INFINITY;
+
+        for _ in 0..iterations {
+            let moved: Vec<Vec3> = source.iter().map(|p| current.apply(*p)).collect();
+            let (closest, d2): (Vec<Vec3>, Vec<f32>) =
+                moved.iter().map(|p| self.closest(*p)).unzip();
+            let error = d2.iter().sum::<f32>() / d2.len().max(1) as f32;
+            // the first pass has nothing to compare with
+            if last.is_finite() && last - error <= 1e-6 * last.max(1e-12) {
+                last = error;
+                break;
+            }
+            last = error;
+            current = best_rigid(&moved, &closest) * current;
+        }
+
+        (current, last)
+    }
+
+    /// Rigid transform taking `source` onto the target: PCA frames for the
+    /// initial guess, trying each axis flip PCA can't tell apart, then ICP
+    pub fn register(&self, source: &Mesh) -> (Rigid, f32) {
+        let points = source.surface_samples(ALIGN_SAMPLES);
+        let frame = pca_frame(&points);
+
+        [
+            Quat::IDENTITY,
+            Quat::from_rotation_x(std::f32::consts::PI),
+            Quat::from_rotation_y(std::f32::consts::PI),
+            Quat::from_rotation_z(std::f32::consts::PI),
+        ]
+        .into_iter()
+        .map(|flip| {
+            let flip = Rigid {
+                rotation: flip,
+                translation: Vec3::ZERO,
+            };
+            let init = self.frame.inverse() * flip * frame;
+            self.icp(&points, init, ICP_ITERATIONS)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((Rigid::IDENTITY, f32::INFINITY))
+    }
+}
+
+/// Scores candidates after registering them onto the target
+pub struct Aligned {
+    inner: Box<dyn Scorer>,
+    aligner: Option<Aligner>,
+}
+
+impl Aligned {
+    pub fn new(inner: Box<dyn Scorer>) -> Self {
+        Self {
+            inner,
+            aligner: None,
+        }
+    }
+}
+
+impl Aligned {
+    fn registered<'a>(&self, candidate: &Candidate<'a>) -> anyhow::Result<Option<Candidate<'a>>> {
+        let Some(aligner) = &self.aligner else {
+            return Ok(None);
+        };
+        let mesh = candidate.mesh()?;
+        let (transform, _) = aligner.register(mesh);
+        Ok(Some(
+            Candidate::with_mesh(
+                candidate.kinds,
+                candidate.params,
+                mesh.transformed(&transform),
+            )
+            .with_transform(transform * *candidate.transform()),
+        ))
+    }
+}
+
+impl Scorer for Aligned {
+    fn prepare(&mut self, target: &Mesh) {
+        self.aligner = Some(Aligner::new(target));
+        self.inner.prepare(target);
+    }
+
+    fn score(&self, candidate: &Candidate) -> anyhow::Result<f32> {
+        match self.registered(candidate)? {
+            Some(moved) => self.inner.score(&moved),
+            None => self.inner.score(candidate),
+        }
+    }
+
+    fn residuals(&self, candidate: &Candidate) -> anyhow::Result<Option<Vec<(Vec3, f32)>>> {
+        match self.registered(candidate)? {
+            Some(moved) => self.inner.residuals(&moved),
+            None => self.inner.residuals(candidate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mesh::tests::cuboid, transform::rotation_from_degrees};
+
+    fn moved() -> Rigid {
+        Rigid {
+            rotation: rotation_from_degrees(30.0, -20.0, 75.0),
+            translation: Vec3::new(1.0, -2.0, 0.5),
+        }
+    }
+
+    #[test]
+    fn jacobi_diagonalizes_a_symmetric_matrix() {
+        let a = [[4.0, 1.0, 2.0], [1.0, 3.0, 0.5], [2.0, 0.5, 5.0]];
+        let (values, vectors) = jacobi_eigen(a);
+        for k in 0..3 {
+            let v = [vectors[0][k], vectors[1][k], vectors[2][k]];
+            for i in 0..3 {
+                let av: f64 = (0..3).map(|j| a[i][j] * v[j]).sum();
+                assert!((av - values[k] * v[i]).abs() < 1e-9, "column {k}");
+            }
+            for l in 0..3 {
+                let dot: f64 = (0..3).map(|i| vectors[i][k] * vectors[i][l]).sum();
+                assert!((dot - (k == l) as u8 as f64).abs() < 1e-9);
+            }
+        }
+        let trace: f64 = values.iter().sum();
+        assert!((trace - 12.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn principal_axes_follow_the_spread() {
+        let points: Vec<Vec3> = (0..20)
+            .flat_map(|i| {
+                let x = i as f32 - 9.5;
+                [Vec3::new(x, 0.5, 0.0), Vec3::new(x, -0.5, 0.0)]
+            })
+            .collect();
+        let (c, axes) = principal_axes(&points);
+        assert!(c.length() < 1e-5);
+        assert!(axes[0].dot(Vec3::X).abs() > 0.999);
+        assert!(axes[1].dot(Vec3::Y).abs() > 0.999);
+        assert!((axes[0].cross(axes[1]) - axes[2]).length() < 1e-5);
+    }
+
+    #[test]
+    fn best_rigid_recovers_a_transform() {
+        let source = [
+            Vec3::ZERO,
+            Vec3::X,
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(1.0, 1.0, 3.0),
+        ];
+        let target: Vec<Vec3> = source.iter().map(|p| moved().apply(*p)).collect();
+        let found = best_rigid(&source, &target);
+        for (s, t) in source.iter().zip(&target) {
+            assert!((found.apply(*s) - *t).length() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn registers_a_moved_box() {
+        let target = cuboid(Vec3::ZERO, Vec3::new(1.0, 2.0, 4.0));
+        let source = target.transformed(&moved().inverse());
+        let (transform, error) = Aligner::new(&target).register(&source);
+        assert!(error < 1e-4, "mse {error}");
+        for p in &source.positions {
+            let q = transform.apply(*p);
+            assert!(
+                target.positions.iter().any(|t| (*t - q).length() < 1e-2),
+                "{q}"
+            );
+        }
+    }
+
+    #[test]
+    fn icp_improves_on_its_initial_guess() {
+        let target = cuboid(Vec3::ZERO, Vec3::new(1.0, 2.0, 4.0));
+        let aligner = Aligner::new(&target);
+        let points = target.surface_samples(ALIGN_SAMPLES);
+        // a guess as far off as PCA is for near-symmetric shapes
+        let init = Rigid {
+            rotation: rotation_from_degrees(0.0, 5.0, 10.0),
+            translation: Vec3::new(0.1, -0.1, 0.05),
+        };
+        let (_, guessed) = aligner.icp(&points, init, 1);
+        let (transform, error) = aligner.icp(&points, init, ICP_ITERATIONS);
+        assert!(error < guessed * 0.01, "mse {error} from {guessed}");
+        assert!(transform.translation.length() < 1e-2);
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::{HashMap, HashSet};
+
+use rerun::external::glam::{DMat3, DVec3, Mat3, Vec3};
+
+use crate::{
+    align::{jacobi_eigen, principal_axes},
+    bvh::Aabb,
+    mesh::Mesh,
+    volume::is_watertight,
+};
+
+/// Volume integrals of a closed mesh at unit density
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Moments {
+    pub volume: f32,
+    pub centroid: Vec3,
+    /// second moments `∫ (p - c)(p - c)ᵀ dV` about the centroid
+    pub covariance: Mat3,
+}
+
+impl Moments {
+    /// Sums the signed tetrahedra between the origin and every triangle, which
+    /// only means something for closed, consistently wound meshes
+    pub fn new(mesh: &Mesh) -> Self {
+        let mut volume = 0.0;
+        let mut first = DVec3::ZERO;
+        let mut second = DMat3::ZERO;
+        for i in 0..mesh.triangles.len() {
+            let [a, b, c] = mesh.triangle(i).map(|v| v.as_dvec3());
+            let v = a.dot(b.cross(c)) / 6.0;
+            let s = a + b + c;
+            volume += v;
+            first += s * (v / 4.0);
+            second += (outer(a) + outer(b) + outer(c) + outer(s)) * (v / 20.0);
+        }
+        if volume.abs() < f64::EPSILON {
+            return Self::default();
+        }
+
+        let centroid = first / volume;
+        let covariance = second - outer(centroid) * volume;
+        Self {
+            volume: volume as f32,
+            centroid: centroid.as_vec3(),
+            covariance: covariance.as_mat3(),
+        }
+    }
+
+    /// Inertia tensor about the centroid, `tr(C) I - C`
+    pub fn inertia(&self) -> Mat3 {
+        let c = self.covariance;
+        Mat3::from_diagonal(Vec3::splat(c.x_axis.x + c.y_axis.y + c.z_axis.z)) - c
+    }
+
+    /// Eigenvalues of the inertia tensor, ascending; unchanged by rotation
+    pub fn principal_moments(&self) -> Vec3 {
+        let i = self.inertia().as_dmat3().to_cols_array_2d();
+        let (mut values, _) = jacobi_eigen(i);
+        values.sort_by(f64::total_cmp);
+        DVec3::from_array(values).as_vec3()
+    }
+}
+
+fn outer(v: DVec3) -> DMat3 {
+    DMat3::from_cols(v * v.x, v * v.y, v * v.z)
+}
+
+/// Box along the principal axes of the vertices, `axes` are unit and right handed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Obb {
+    pub center: Vec3,
+    pub axes: [Vec3; 3],
+    pub half_extents: Vec3,
+}
+
+impl Obb {
+    pub fn new(points: &[Vec3]) -> Self {
+        let (c, axes) = principal_axes(points);
+        let mut low = Vec3::splat(f32::INFINITY);
+        let mut high = Vec3::splat(f32::NEG_INFINITY);
+        for p in points {
+            let local = Vec3::from_array(axes.map(|a| (*p - c).dot(a)));
+            low = low.min(local);
+            high = high.max(local);
+        }
+        let mid = (low + high) * 0.5;
+        Self {
+            center: c + axes[0] * mid.x + axes[1] * mid.y + axes[2] * mid.z,
+            axes,
+            half_extents: (high - low) * 0.5,
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        8.0 * self.half_extents.x * self.half_extents.y * self.half_extents.z
+    }
+
+    /// Full side lengths, longest first
+    pub fn sorted_extents(&self) -> Vec3 {
+        let mut e = (self.half_extents * 2.0).to_array();
+        e.sort_by(|a, b| b.total_cmp(a));
+        Vec3::from_array(e)
+    }
+}
+
+/// Everything we routinely want to know about a target or candidate
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis {
+    pub moments: Moments,
+    pub area: f32,
+    pub aabb: Aabb,
+    pub obb: Obb,
+    pub components: usize,
+    /// `V - E + F` over welded vertices, 2 per closed genus 0 component
+    pub euler_characteristic: i64,
+    pub watertight: bool,
+}
+
+impl Analysis {
+    pub fn new(mesh: &Mesh) -> Self {
+        Self {
+            moments: Moments::new(mesh),
+            area: (0..mesh.triangles.len())
+                .map(|i| mesh.triangle_area(i))
+                .sum(),
+            aabb: Aabb::from_points(&mesh.positions),
+            obb: Obb::new(&mesh.positions),
+            components: mesh.components(),
+            euler_characteristic: euler_characteristic(mesh),
+            watertight: is_watertight(mesh),
+        }
+    }
+
+    /// Handles per component, from the Euler characteristic of a closed surface
+    pub fn genus(&self) -> Option<i64> {
+        (self.watertight && self.components > 0)
+            .then(|| self.components as i64 - self.euler_characteristic / 2)
+    }
+}
+
+pub fn euler_characteristic(mesh: &Mesh) -> i64 {
+    let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+    let ids: Vec<u32> = mesh
+        .positions
+        .iter()
+        .map(|p| {
+            let n = welded.len() as u32;
+            *welded.entry(p.to_array().map(f32::to_bits)).or_insert(n)
+        })
+        .collect();
+
+    let mut vertices = HashSet::new();
+    let mut edges = HashSet::new();
+    for t in &mesh.triangles {
+        let t = t.map(|v| ids[v as usize]);
+        vertices.extend(t);
+        for (u, v) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+            edges.insert((u.min(v), u.max(v)));
+        }
+    }
+    vertices.len() as i64 - edges.len() as i64 + mesh.triangles.len() as i64
+}
+
+/// Two points far apart, the first guess of Ritter's sphere
+fn far_pair(points: &[Vec3]) -> Option<(Vec3, Vec3)> {
+    let &first = points.first()?;
+    let farthest = |from: Vec3| {
+        points
+            .iter()
+            .copied()
+            .max_by(|a, b| {
+                a.distance_squared(from)
+                    .total_cmp(&b.distance_squared(from))
+            })
+            .unwrap_or(from)
+    };
+    let a = farthest(first);
+    Some((a, farthest(a)))
+}
+
+/// Half the distance between two points far apart, never more than the
+/// radius of the smallest sphere around every point
+pub fn spread_radius(points: &[Vec3]) -> f32 {
+    far_pair(points).map_or(0.0, |(a, b)| a.distance(b) * 0.5)
+}
+
+/// Radius of a sphere around every point, Ritter's: never less than the
+/// smallest one's, usually within a fifth of it and, like it, unchanged by
+/// rotation
+pub fn bounding_radius(points: &[Vec3]) -> f32 {
+    let Some((a, b)) = far_pair(points) else {
+        return 0.0;
+    };
+    let mut center = (a + b) * 0.5;
+    let mut radius = a.distance(b) * 0.5;
+    for p in points {
+        let d = p.distance(center);
+        if d > radius {
+            let grown = (radius + d) * 0.5;
+            center += (*p - center) * ((grown - radius) / d);
+            radius = grown;
+        }
+    }
+    radius
+}
+
+/// Cheap test for candidates that can't be (part of) the target: adding
+/// primitives by union only grows volume, bounding sphere and principal
+/// moments, so a candidate already beyond the target's by more than `slack` is
+/// rejected before any distance is computed. The candidate's sphere is
+/// bounded from below by [`spread_radius`] and the target's from above by
+/// [`bounding_radius`], so that test never rejects a part of the target,
+/// however small the slack. Only rotation invariant
+/// quantities are compared, principal axes flip between near symmetric
+/// shapes. None of this holds for targets with intersections or for partial
+/// scans, which can be smaller than their parts
+#[derive(Clone, Debug)]
+pub struct Prefilter {
+    slack: f32,
+    volume: Option<f32>,
+    radius: f32,
+    principal_moments: Option<Vec3>,
+}
+
+impl Prefilter {
+    /// Volume and moments are only used for watertight targets, point clouds
+    /// and open scans are judged by their bounding sphere alone
+    pub fn new(target: &Mesh, slack: f32) -> Self {
+        let closed = is_watertight(target);
+        let moments = Moments::new(target);
+        Self {
+            slack,
+            volume: closed.then_some(moments.volume.abs()),
+            radius: bounding_radius(&target.positions),
+            principal_moments: closed.then(|| moments.principal_moments()),
+        }
+    }
+
+    pub fn accepts(&self, candidate: &Mesh) -> bool {
+        if candidate.is_empty() {
+            return true;
+        }
+        if spread_radius(&candidate.positions) > self.radius * self.slack {
+            return false;
+        }
+        if self.volume.is_none() && self.principal_moments.is_none() {
+            return true;
+        }
+
+        let moments = Moments::new(candidate);
+        if self
+            .volume
+            .is_some_and(|v| moments.volume.abs() > v * self.slack)
+        {
+            return false;
+        }
+        // second moments go with volume times length squared
+        let slack = self.slack.powi(3);
+        !self
+            .principal_moments
+            .is_some_and(|m| moments.principal_moments().cmpgt(m * slack).any())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mesh::tests::{cuboid, unit_cube},
+        transform::{rotation_from_degrees, Rigid},
+    };
+
+    fn brick() -> Mesh {
+        cuboid(Vec3::ZERO, Vec3::new(2.0, 3.0, 4.0))
+    }
+
+    fn turned(mesh: &Mesh) -> Mesh {
+        mesh.transformed(&Rigid {
+            rotation: rotation_from_degrees(30.0, 45.0, -60.0),
+            translation: Vec3::new(5.0, -1.0, 2.0),
+        })
+    }
+
+    #[test]
+    fn moments_of_a_box() {
+        let moments = Moments::new(&brick());
+        assert!((moments.volume - 24.0).abs() < 1e-4);
+        assert!((moments.centroid - Vec3::new(1.0, 1.5, 2.0)).length() < 1e-5);
+        // V a² / 12 along each side a
+        let diagonal = Vec3::new(
+            moments.covariance.x_axis.x,
+            moments.covariance.y_axis.y,
+            moments.covariance.z_axis.z,
+        );
+        assert!((diagonal - Vec3::new(8.0, 18.0, 32.0)).length() < 1e-3);
+        assert!((moments.principal_moments() - Vec3::new(26.0, 40.0, 50.0)).length() < 1e-3);
+    }
+
+    #[test]
+    fn invariants_survive_rotation() {
+        let (a, b) = (brick(), turned(&brick()));
+        let (ma, mb) = (Moments::new(&a), Moments::new(&b));
+        assert!((ma.volume - mb.volume).abs() < 1e-3);
+        assert!((ma.principal_moments() - mb.principal_moments()).length() < 1e-2);
+        assert!((bounding_radius(&a.positions) - bounding_radius(&b.positions)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn bounding_sphere_is_close_to_the_smallest() {
+        let smallest = 29.0f32.sqrt() / 2.0;
+        let radius = bounding_radius(&brick().positions);
+        assert!(
+            radius >= smallest - 1e-5 && radius <= smallest * 1.2,
+            "{radius}"
+        );
+        assert_eq!(bounding_radius(&[]), 0.0);
+        assert_eq!(bounding_radius(&[Vec3::ONE]), 0.0);
+        let spread = spread_radius(&brick().positions);
+        assert!(spread <= smallest + 1e-5 && spread <= radius, "{spread}");
+        assert_eq!(spread_radius(&[]), 0.0);
+    }
+
+    #[test]
+    fn topology_of_a_box() {
+        let analysis = Analysis::new(&brick());
+        assert!(analysis.watertight);
+        assert_eq!(analysis.components, 1);
+        assert_eq!(analysis.euler_characteristic, 2);
+        assert_eq!(analysis.genus(), Some(0));
+        assert!((analysis.area - 52.0).abs() < 1e-4);
+        assert!((analysis.obb.volume() - 24.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn prefilter_ignores_orientation() {
+        let prefilter = Prefilter::new(&brick(), 1.05);
+        assert!(prefilter.accepts(&brick()));
+        assert!(prefilter.accepts(&turned(&brick())));
+        assert!(prefilter.accepts(&unit_cube()));
+        assert!(prefilter.accepts(&Mesh::default()));
+        assert!(!prefilter.accepts(&cuboid(Vec3::ZERO, Vec3::splat(4.0))));
+    }
+
+    #[test]
+    fn open_targets_use_the_sphere_alone() {
+        let points = Mesh {
+            positions: brick().positions,
+            triangles: vec![],
+        };
+        let prefilter = Prefilter::new(&points, 1.05);
+        assert!(prefilter.accepts(&cuboid(Vec3::ZERO, Vec3::new(4.5, 0.1, 0.1))));
+        // the target's own points pass with no slack beyond rounding
+        assert!(Prefilter::new(&turned(&points), 1.001).accepts(&brick()));
+        assert!(!prefilter.accepts(&cuboid(Vec3::ZERO, Vec3::splat(4.0))));
+    }
+}
@@ -1,13 +1,21 @@
+use clap::Parser;
 use paramesh::{
-    chamfer_distance, generate_random,
-    microcad::{generate, Microcad},
+    align::{Aligned, Aligner},
+    analysis::Prefilter,
+    cloud::CloudFilter,
+    generator::GeneratorConfig,
+    io,
+    mesh::{Mesh, Renderer},
+    microcad::generate,
+    optimize::{polish, OptimizeConfig, Optimizer, ParamBounds},
+    scan::Scanner,
+    score::{Candidate, ScoreSpec, Scorer},
+    transform::{Normalize, Rigid},
+    visualize,
 };
 use rand::prelude::*;
-use rerun::{
-    external::glam::{self, Vec3},
-    RecordingStream,
-};
-use std::collections::VecDeque;
+use rerun::RecordingStream;
+use std::path::PathBuf;
 
 /// Compute centroid of a set of 3D points
 fn compute_centroid(points: &[[f32; 3]]) -> [f32; 3] {
@@ -60,7 +68,7 @@ fn shape_heuristic(bbox_sizes: [f32; 3]) -> u8 {
 
 /// Convert f32 to f32 for your parameter array
 fn f32_to_f32_clamped(x: f32) -> f32 {
-    x.round().clamp(1.0, 20.0) as f32
+    x.round().clamp(1.0, 20.0)
 }
 
 #[derive(Clone, Debug, Copy)]
@@ -81,8 +89,55 @@ type Program = Vec<(u8, [f32; 10])>;
 struct Cegis {
     sketch: Sketch,
     constraints: Vec<Constraint>,
-    target: Vec<Vec3>,
+    target: Mesh,
+    scorer: Box<dyn Scorer>,
+    prefilter: Option<Prefilter>,
+    renderer: Renderer,
     rec: RecordingStream,
+    /// best score shown in the viewer so far
+    shown: f32,
+}
+
+#[derive(Parser)]
+struct Args {
+    /// mesh (STL, OBJ, PLY), point cloud (XYZ, PCD, PLY) or µcad program to
+    /// reverse engineer, a random program otherwise
+    #[arg(long)]
+    target: Option<PathBuf>,
+    /// scorer used to rank programs, e.g. `surface+0.1*complexity`
+    #[arg(long, default_value = "chamfer")]
+    score: ScoreSpec,
+    /// register programs onto the target before scoring, for targets in
+    /// arbitrary poses, and print the result in the target's frame
+    #[arg(long)]
+    align: bool,
+    /// refine the final program with a continuous optimizer
+    #[arg(long, value_enum)]
+    polish: Option<Optimizer>,
+    /// move and scale the target before searching, programs are printed back
+    /// in its original units
+    #[arg(long, value_enum, default_value_t)]
+    normalize: Normalize,
+    #[command(flatten)]
+    cloud: CloudFilter,
+    /// replace the target by a simulated scan of it
+    #[arg(long)]
+    scan: bool,
+    #[command(flatten)]
+    scanner: Scanner,
+    #[command(flatten)]
+    generator: GeneratorConfig,
+    /// skip candidates whose bounding sphere, volume or moments exceed the
+    /// target's by this factor before scoring them, 0 scores everything; wrong
+    /// for targets with intersections and for partial scans
+    #[arg(long, default_value_t = 0.0)]
+    prefilter: f32,
+    /// how programs are turned into meshes
+    #[arg(long, value_enum, default_value_t)]
+    renderer: Renderer,
+    /// write the final program's mesh, in the target's units, to this STL, OBJ or PLY file
+    #[arg(long)]
+    export: Option<PathBuf>,
 }
 
 fn refine_once(
@@ -140,13 +195,13 @@ fn refine_once(
 }
 
 pub fn local_search(
-    mut kind: u8,
-    mut params: [f32; 10],
+    kind: u8,
+    params: [f32; 10],
     score_fn: &impl Fn(u8, [f32; 10]) -> f32,
     rng: &mut impl Rng,
     restarts: usize,
 ) -> (u8, [f32; 10], f32) {
-    let (mut best_kind, mut best_params, mut best_score) = refine_once(kind, params, score_fn);
+    let (best_kind, mut best_params, mut best_score) = refine_once(kind, params, score_fn);
 
     for _ in 0..restarts {
         let k = best_kind;
@@ -154,26 +209,26 @@ pub fn local_search(
 
         match k {
             0 => {
-                for i in 0..3 {
-                    p[i] = p[i] + rng.random_range(-4.0..=4.0);
+                for v in &mut p[..3] {
+                    *v += rng.random_range(-4.0..=4.0);
                 }
             }
             1 => {
-                p[0] = p[0] + rng.random_range(-4.0..=4.0);
+                p[0] += rng.random_range(-4.0..=4.0);
             }
             2 => {
-                p[0] = p[0] + rng.random_range(-4.0..=4.0);
-                p[1] = p[1] + rng.random_range(-4.0..=4.0);
+                p[0] += rng.random_range(-4.0..=4.0);
+                p[1] += rng.random_range(-4.0..=4.0);
             }
             _ => {}
         }
 
-        for i in 3..6 {
-            p[i] = p[i] + rng.random_range(-4.0..=4.);
+        for v in &mut p[3..6] {
+            *v += rng.random_range(-4.0..=4.);
         }
 
-        for i in 6..9 {
-            p[i] = p[i] + rng.random_range(-3.0..=3.);
+        for v in &mut p[6..9] {
+            *v += rng.random_range(-3.0..=3.);
         }
 
         let (_, refined_params, refined_score) = refine_once(k, p, score_fn);
@@ -188,25 +243,24 @@ pub fn local_search(
 }
 
 impl Cegis {
-    fn new() -> Self {
-        let mut rng = rand::rng();
-
-        let (kinds, params): (Vec<u8>, Vec<[f32; 10]>) =
-            (0..2).map(|_| generate_random(&mut rng)).collect();
-        println!("target: {kinds:?}, {params:?}");
-        let params = params.into_iter().flatten().collect::<Vec<_>>();
-        let target = params_to_glam(&kinds, &params);
+    fn new(target: Mesh, mut scorer: Box<dyn Scorer>, prefilter: f32, renderer: Renderer) -> Self {
         let rec = rerun::RecordingStreamBuilder::new("microcad synthesizer")
             .spawn()
             .unwrap();
-        let points = rerun::Points3D::new(target.clone());
+        let points = rerun::Points3D::new(target.positions.clone());
         rec.log("target", &points.with_radii([0.1])).unwrap();
+        scorer.prepare(&target);
+        let prefilter = (prefilter > 0.0).then(|| Prefilter::new(&target, prefilter));
 
         Self {
             sketch: Vec::new(),
             constraints: Vec::new(),
             target,
+            scorer,
+            prefilter,
+            renderer,
             rec,
+            shown: f32::INFINITY,
         }
     }
 
@@ -241,8 +295,8 @@ impl Cegis {
         for i in 0..3 {
             params[i + 3] = f32_to_f32_clamped(centroid[i] + rng.random_range(-1.0..=1.0));
         }
-        for i in 6..9 {
-            params[i] = rng.random_range(0.0..=20.0);
+        for v in &mut params[6..9] {
+            *v = rng.random_range(0.0..=20.0);
         }
 
         params[9] = rng.random_range(0.0..=1.0);
@@ -254,10 +308,10 @@ impl Cegis {
         // Placeholder: you should implement distance-based residuals or feature-based
         // For example: collect points in target_mesh not covered by program
         let mut residual_points = Vec::new();
-        for v in &self.target {
+        for v in &self.target.positions {
             let mut covered = false;
             for &(_, p) in program {
-                let pc = [p[3] as f32, p[4] as f32, p[5] as f32];
+                let pc = [p[3], p[4], p[5]];
                 let dist_sq = (v.x - pc[0]).powi(2) + (v.y - pc[1]).powi(2) + (v.z - pc[2]).powi(2);
 
                 if dist_sq.sqrt() <= 5.0 {
@@ -274,15 +328,29 @@ impl Cegis {
         vec![Constraint { residual_points }]
     }
 
-    fn score_program(&self, program: &Program) -> f32 {
+    fn score_program(&mut self, program: &Program) -> f32 {
         // Wrap your score_k_p function here
         let (kinds, params): (Vec<u8>, Vec<[f32; 10]>) = program.iter().cloned().unzip();
         let flat_params: Vec<f32> = params.into_iter().flatten().collect();
-        let b = params_to_glam(&kinds, &flat_params);
-
-        visualize(&b, &self.rec);
-
-        chamfer_distance(&self.target, &b)
+        let candidate = Candidate::new(&kinds, &flat_params).with_renderer(self.renderer);
+        if let Some(prefilter) = &self.prefilter {
+            match candidate.mesh() {
+                Ok(mesh) if prefilter.accepts(mesh) => {}
+                _ => return f32::INFINITY,
+            }
+        }
+        // a program that fails to render or score ranks last
+        let score = self.scorer.score(&candidate).unwrap_or(f32::INFINITY);
+
+        // only new bests are rendered for the viewer, which keeps the sdf
+        // scorer mesh free
+        if score < self.shown {
+            self.shown = score;
+            if let Ok(mesh) = candidate.mesh() {
+                visualize(mesh.positions.clone(), &self.rec);
+            }
+        }
+        score
     }
 
     fn run(&mut self, max_primitives: usize, max_attempts_per_hole: usize) -> Program {
@@ -337,15 +405,86 @@ impl Cegis {
 }
 
 fn main() {
-    let mut cegis = Cegis::new();
+    let args = Args::parse();
+    let mut scorer = args.score.build();
+    if args.align {
+        scorer = Box::new(Aligned::new(scorer));
+    }
+    let target = match &args.target {
+        Some(path) => io::load_target(path, &args.cloud).unwrap(),
+        None => {
+            let mut rng = rand::rng();
+            let (kinds, params) = args.generator.valid_program(&mut rng).unwrap();
+            println!("target: {kinds:?}, {params:?}");
+            args.renderer.render(&kinds, &params).unwrap()
+        }
+    };
+    let target: Mesh = if args.scan {
+        let cloud = args.cloud.apply(&args.scanner.scan(&target));
+        println!("scanned: {} points", cloud.len());
+        cloud.into()
+    } else {
+        target
+    };
+    let scaling = args.normalize.fit(&target, &ParamBounds::default());
+    if !scaling.is_identity() {
+        println!("normalized: {scaling}");
+    }
+    // a partial scan or an intersection can be smaller than its parts
+    let prefilter = match args.scan || args.generator.intersections > 0.0 {
+        true => 0.0,
+        false => args.prefilter,
+    };
+    let mut cegis = Cegis::new(scaling.mesh(&target), scorer, prefilter, args.renderer);
 
     cegis.constraints = Vec::new();
     cegis.sketch = vec![];
 
     let final_program = cegis.run(10, 100);
 
-    let (k, p): (Vec<u8>, Vec<[f32; 10]>) = final_program.into_iter().unzip();
+    let (k, mut p): (Vec<u8>, Vec<[f32; 10]>) = final_program.into_iter().unzip();
     println!("result: {k:?}, {p:?}");
+    if let Some(optimizer) = args.polish {
+        let config = OptimizeConfig {
+            optimizer,
+            renderer: args.renderer,
+            ..Default::default()
+        };
+        let flat = p.iter().flatten().copied().collect::<Vec<_>>();
+        let (polished, score) = polish(
+            &k,
+            &flat,
+            cegis.scorer.as_ref(),
+            &ParamBounds::default(),
+            &config,
+        );
+        p = polished
+            .chunks_exact(10)
+            .map(|p| p.try_into().unwrap())
+            .collect();
+        println!("polished ({score}): {k:?}, {p:?}");
+    }
+    let p = p.iter().flatten().copied().collect::<Vec<_>>();
+    let transform = if args.align {
+        let (transform, error) =
+            Aligner::new(&cegis.target).register(&args.renderer.render(&k, &p).unwrap());
+        println!("registered: {transform} (mse {error})");
+        transform
+    } else {
+        Rigid::IDENTITY
+    };
+    if !transform.is_identity() || !scaling.is_identity() {
+        println!(
+            "{}",
+            generate::ucad_unscaled(&k, &p, &transform, &scaling).unwrap()
+        );
+    }
+    if let Some(path) = &args.export {
+        let mesh = args.renderer.render(&k, &p).unwrap();
+        let mesh = scaling.inverse().mesh(&mesh.transformed(&transform));
+        io::save_mesh(path, &mesh).unwrap();
+        println!("exported {}", path.display());
+    }
     // let p = p.into_iter().flatten().collect::<Vec<_>>();
     // let glam = params_to_glam(&k, &p);
     // let rec = rerun::RecordingStreamBuilder::new("microcad synthesizer")
@@ -354,16 +493,3 @@ fn main() {
     // let points = rerun::Points3D::new(glam.clone());
     // rec.log("result", &points.with_radii([0.1])).unwrap();
 }
-
-fn params_to_glam(kinds: &[u8], params: &[f32]) -> Vec<Vec3> {
-    let ucad = generate::ucad(kinds, params).unwrap();
-    let mut target = Microcad::new();
-    target.set_root(&ucad);
-    let triags = target.render_mesh().unwrap();
-    let pos = triags
-        .positions
-        .iter()
-        .map(|v| glam::vec3(v.x, v.y, v.z))
-        .collect();
-    pos
-}
@@ -0,0 +1,211 @@
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use paramesh::{
+    generator::GeneratorConfig,
+    mesh::params_to_mesh,
+    score::{Candidate, ScoreSpec, Scorer},
+    sdf::cross_check,
+};
+use rand::prelude::*;
+
+/// Checks that the analytic SDF scorer ranks candidates like the mesh scorer
+#[derive(Parser)]
+struct Args {
+    /// random target programs
+    #[arg(long, default_value_t = 5)]
+    programs: usize,
+    /// candidates ranked per target, from slight perturbations to unrelated programs
+    #[arg(long, default_value_t = 20)]
+    candidates: usize,
+    #[arg(long, default_value = "chamfer")]
+    mesh_score: ScoreSpec,
+    #[arg(long, default_value = "sdf")]
+    sdf_score: ScoreSpec,
+    /// fail when the mean rank correlation is lower
+    #[arg(long, default_value_t = 0.8)]
+    min_correlation: f32,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    #[command(flatten)]
+    generator: GeneratorConfig,
+}
+
+/// Rank of every value, ties share their mean rank
+fn ranks(values: &[f32]) -> Vec<f32> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        for &k in &order[i..=j] {
+            ranks[k] = (i + j) as f32 * 0.5;
+        }
+        i = j + 1;
+    }
+    ranks
+}
+
+/// Spearman's rho, Pearson correlation of the ranks
+fn spearman(a: &[f32], b: &[f32]) -> f32 {
+    let (ra, rb) = (ranks(a), ranks(b));
+    let n = ra.len() as f32;
+    let (ma, mb) = (ra.iter().sum::<f32>() / n, rb.iter().sum::<f32>() / n);
+    let mut cov = 0.0;
+    let (mut va, mut vb) = (0.0, 0.0);
+    for (x, y) in ra.iter().zip(&rb) {
+        cov += (x - ma) * (y - mb);
+        va += (x - ma).powi(2);
+        vb += (y - mb).powi(2);
+    }
+    cov / (va * vb).sqrt().max(f32::EPSILON)
+}
+
+fn argmin(values: &[f32]) -> usize {
+    (0..values.len())
+        .min_by(|a, b| values[*a].total_cmp(&values[*b]))
+        .unwrap_or_default()
+}
+
+/// The target with every parameter but the op moved by up to `amount` of its range
+fn perturb(
+    rng: &mut impl Rng,
+    params: &[f32],
+    generator: &GeneratorConfig,
+    amount: f32,
+) -> Vec<f32> {
+    let bounds = generator.bounds();
+    let mut params = params.to_vec();
+    for primitive in params.chunks_exact_mut(10) {
+        for (i, p) in primitive.iter_mut().take(9).enumerate() {
+            let (low, high) = match i {
+                0..3 => bounds.size,
+                3..6 => bounds.translation,
+                _ => bounds.rotation,
+            };
+            *p += rng.random_range(-1.0..=1.0) * amount * (high - low);
+            if i < 3 {
+                *p = p.max(low);
+            }
+        }
+    }
+    params
+}
+
+/// Scores perturbed and unrelated candidates of random targets both ways and
+/// returns the mean rank correlation
+fn verify(args: &Args) -> anyhow::Result<f32> {
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(args.seed);
+    let mut mesh_scorer = args.mesh_score.build();
+    let mut sdf_scorer = args.sdf_score.build();
+
+    let mut correlations = vec![];
+    let mut top_matches = 0;
+    let (mut mesh_time, mut sdf_time) = (Duration::ZERO, Duration::ZERO);
+    for program in 0..args.programs {
+        let (kinds, params) = args.generator.valid_program(&mut rng)?;
+        let agreement = cross_check(&kinds, &params, 4096)?;
+        let target = params_to_mesh(&kinds, &params)?;
+        mesh_scorer.prepare(&target);
+        sdf_scorer.prepare(&target);
+
+        let (mut by_mesh, mut by_sdf) = (vec![], vec![]);
+        for i in 0..args.candidates {
+            let amount = i as f32 / args.candidates as f32;
+            let (k, p) = if i % 4 == 3 {
+                args.generator.program(&mut rng)
+            } else {
+                (
+                    kinds.clone(),
+                    perturb(&mut rng, &params, &args.generator, amount),
+                )
+            };
+
+            let start = Instant::now();
+            by_mesh.push(score(mesh_scorer.as_ref(), &k, &p));
+            mesh_time += start.elapsed();
+
+            let start = Instant::now();
+            by_sdf.push(score(sdf_scorer.as_ref(), &k, &p));
+            sdf_time += start.elapsed();
+        }
+
+        let rho = spearman(&by_mesh, &by_sdf);
+        let same_best = argmin(&by_mesh) == argmin(&by_sdf);
+        top_matches += same_best as usize;
+        correlations.push(rho);
+        println!(
+            "program {program}: {kinds:?} inside/outside agreement {agreement:.3}, \
+             rank correlation {rho:.3}, same best {same_best}"
+        );
+    }
+
+    let mean = correlations.iter().sum::<f32>() / correlations.len().max(1) as f32;
+    println!(
+        "mean rank correlation {mean:.3}, same best {top_matches}/{}, \
+         mesh {mesh_time:?} vs sdf {sdf_time:?}",
+        args.programs
+    );
+    Ok(mean)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let mean = verify(&args)?;
+    if mean < args.min_correlation {
+        Err(anyhow::anyhow!(
+            "rank correlation {mean:.3} below {}",
+            args.min_correlation
+        ))?
+    }
+    Ok(())
+}
+
+/// Failed renders rank last rather than aborting the run
+fn score(scorer: &dyn Scorer, kinds: &[u8], params: &[f32]) -> f32 {
+    scorer
+        .score(&Candidate::new(kinds, params))
+        .unwrap_or(f32::INFINITY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ties_share_their_mean_rank() {
+        assert_eq!(ranks(&[3.0, 1.0, 3.0, 2.0]), vec![2.5, 0.0, 2.5, 1.0]);
+        assert_eq!(ranks(&[]), Vec::<f32>::new());
+    }
+
+    #[test]
+    fn rank_correlation_ignores_scale() {
+        let a = [1.0, 2.0, 3.0, 4.0];
+        assert!((spearman(&a, &[10.0, 20.0, 300.0, 4000.0]) - 1.0).abs() < 1e-6);
+        assert!((spearman(&a, &[4.0, 3.0, 2.0, 1.0]) + 1.0).abs() < 1e-6);
+        assert_eq!(spearman(&a, &[1.0; 4]), 0.0);
+        assert_eq!(argmin(&[2.0, f32::INFINITY, -1.0]), 2);
+    }
+
+    #[test]
+    fn field_agrees_with_renders() {
+        let args = Args::parse_from(["sdf_verify"]);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(1);
+        for _ in 0..3 {
+            let (kinds, params) = args.generator.valid_program(&mut rng).unwrap();
+            let agreement = cross_check(&kinds, &params, 1024).unwrap();
+            assert!(agreement > 0.95, "{kinds:?} {params:?}: {agreement}");
+        }
+    }
+
+    #[test]
+    fn sdf_ranks_like_the_mesh_scorer() {
+        let args = Args::parse_from(["sdf_verify", "--programs", "2", "--candidates", "12"]);
+        let mean = verify(&args).unwrap();
+        assert!(mean >= args.min_correlation, "{mean}");
+    }
+}
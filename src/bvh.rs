@@ -0,0 +1,459 @@
+use rerun::external::glam::Vec3;
+
+use crate::mesh::Mesh;
+
+const LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self {
+            min: Vec3::INFINITY,
+            max: Vec3::NEG_INFINITY,
+        }
+    }
+}
+
+impl Aabb {
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let mut aabb = Self::default();
+        for p in points {
+            aabb.grow(*p);
+        }
+        aabb
+    }
+
+    pub fn grow(&mut self, p: Vec3) {
+        self.min = self.min.min(p);
+        self.max = self.max.max(p);
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// No point inside, as for the default box or one with NaN corners
+    pub fn is_empty(&self) -> bool {
+        !self.min.cmple(self.max).all()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn distance_squared(&self, p: Vec3) -> f32 {
+        (self.min - p)
+            .max(p - self.max)
+            .max(Vec3::ZERO)
+            .length_squared()
+    }
+
+    /// Slab test, entry distance along the ray if it hits the box
+    pub fn ray_entry(&self, origin: Vec3, inv_dir: Vec3) -> Option<f32> {
+        let t1 = (self.min - origin) * inv_dir;
+        let t2 = (self.max - origin) * inv_dir;
+        let enter = t1.min(t2).max_element().max(0.0);
+        let exit = t1.max(t2).min_element();
+        (exit >= enter).then_some(enter)
+    }
+}
+
+/// Closest point to `p` on triangle `[a, b, c]` (Ericson, Real-Time Collision Detection 5.1.5)
+pub fn closest_point_on_triangle(p: Vec3, [a, b, c]: [Vec3; 3]) -> Vec3 {
+    let ab = b - a;
+    let ac = c - a;
+
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let sum = va + vb + vc;
+    if sum <= 0.0 {
+        // degenerate triangle, every region test above was inconclusive
+        return a;
+    }
+    a + ab * (vb / sum) + ac * (vc / sum)
+}
+
+/// Möller–Trumbore, returns the ray distance and the determinant whose sign
+/// tells which side of the triangle the ray came from
+pub fn ray_triangle(origin: Vec3, dir: Vec3, [a, b, c]: [Vec3; 3]) -> Option<(f32, f32)> {
+    let e1 = b - a;
+    let e2 = c - a;
+    let h = dir.cross(e2);
+    let det = e1.dot(h);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let inv = 1.0 / det;
+    let s = origin - a;
+    let u = inv * s.dot(h);
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(e1);
+    let v = inv * dir.dot(q);
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = inv * e2.dot(q);
+    (t > 0.0).then_some((t, det))
+}
+
+/// Fixed, deliberately skewed direction for parity tests so rays rarely graze
+/// the axis-aligned edges primitives are made of
+const PARITY_DIR: Vec3 = Vec3::new(0.538_344, 0.613_713, 0.577_658);
+
+#[derive(Clone, Debug)]
+enum Node {
+    Leaf {
+        bounds: Aabb,
+        start: usize,
+        end: usize,
+    },
+    Inner {
+        bounds: Aabb,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Inner { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Bounding volume hierarchy over the triangles of a [`Mesh`]
+#[derive(Clone, Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    triangles: Vec<[Vec3; 3]>,
+}
+
+impl Bvh {
+    pub fn new(mesh: &Mesh) -> Self {
+        let mut triangles: Vec<[Vec3; 3]> = (0..mesh.triangles.len())
+            .map(|i| mesh.triangle(i))
+            .collect();
+        let mut nodes = Vec::new();
+        if !triangles.is_empty() {
+            let len = triangles.len();
+            build(&mut nodes, &mut triangles, 0, len);
+        }
+        Self { nodes, triangles }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    pub fn triangles(&self) -> &[[Vec3; 3]] {
+        &self.triangles
+    }
+
+    /// Closest point on the surface and its squared distance to `p`
+    pub fn closest_point(&self, p: Vec3) -> Option<(Vec3, f32)> {
+        let mut best: Option<(Vec3, f32)> = None;
+        let mut best_d = f32::INFINITY;
+        let mut stack = vec![0];
+
+        while let Some(i) = stack.pop() {
+            let Some(node) = self.nodes.get(i) else {
+                continue;
+            };
+            if node.bounds().distance_squared(p) >= best_d {
+                continue;
+            }
+            match *node {
+                Node::Leaf { start, end, .. } => {
+                    for tri in &self.triangles[start..end] {
+                        let q = closest_point_on_triangle(p, *tri);
+                        let d = (p - q).length_squared();
+                        if d < best_d {
+                            best_d = d;
+                            best = Some((q, d));
+                        }
+                    }
+                }
+                Node::Inner { left, right, .. } => {
+                    let dl = self.nodes[left].bounds().distance_squared(p);
+                    let dr = self.nodes[right].bounds().distance_squared(p);
+                    // visit the nearer child first
+                    if dl < dr {
+                        stack.push(right);
+                        stack.push(left);
+                    } else {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                }
+            }
+        }
+
+        best
+    }
+
+    pub fn distance_squared(&self, p: Vec3) -> f32 {
+        self.closest_point(p).map_or(f32::INFINITY, |(_, d)| d)
+    }
+
+    fn visit_ray(&self, origin: Vec3, dir: Vec3, max_t: f32, mut f: impl FnMut(f32, f32) -> f32) {
+        let inv_dir = dir.recip();
+        let mut max_t = max_t;
+        let mut stack = vec![0];
+
+        while let Some(i) = stack.pop() {
+            let Some(node) = self.nodes.get(i) else {
+                continue;
+            };
+            match node.bounds().ray_entry(origin, inv_dir) {
+                Some(t) if t <= max_t => {}
+                _ => continue,
+            }
+            match *node {
+                Node::Leaf { start, end, .. } => {
+                    for tri in &self.triangles[start..end] {
+                        if let Some((t, det)) = ray_triangle(origin, dir, *tri) {
+                            if t <= max_t {
+                                max_t = f(t, det);
+                            }
+                        }
+                    }
+                }
+                Node::Inner { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+    }
+
+    /// Nearest hit along the ray
+    pub fn raycast(&self, origin: Vec3, dir: Vec3) -> Option<f32> {
+        let mut best = None;
+        self.visit_ray(origin, dir, f32::INFINITY, |t, _| {
+            best = Some(t);
+            t
+        });
+        best
+    }
+
+    /// Sorted distances at which the ray crosses the surface; hits on the
+    /// shared edge of two coplanar-facing triangles are counted once
+    pub fn crossings(&self, origin: Vec3, dir: Vec3) -> Vec<f32> {
+        let mut hits = Vec::new();
+        self.visit_ray(origin, dir, f32::INFINITY, |t, det| {
+            hits.push((t, det > 0.0));
+            f32::INFINITY
+        });
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        hits.dedup_by(|b, a| (b.0 - a.0).abs() < 1e-5 * a.0.max(1.0) && a.1 == b.1);
+        hits.into_iter().map(|(t, _)| t).collect()
+    }
+
+    /// Parity test, only meaningful for closed meshes
+    pub fn contains(&self, p: Vec3) -> bool {
+        self.crossings(p, PARITY_DIR).len() % 2 == 1
+    }
+}
+
+fn build(nodes: &mut Vec<Node>, triangles: &mut [[Vec3; 3]], start: usize, end: usize) -> usize {
+    let mut bounds = Aabb::default();
+    let mut centroids = Aabb::default();
+    for tri in &triangles[start..end] {
+        for v in tri {
+            bounds.grow(*v);
+        }
+        centroids.grow((tri[0] + tri[1] + tri[2]) / 3.0);
+    }
+
+    let index = nodes.len();
+    if end - start <= LEAF_SIZE {
+        nodes.push(Node::Leaf { bounds, start, end });
+        return index;
+    }
+
+    let size = centroids.size();
+    let axis = if size.x >= size.y && size.x >= size.z {
+        0
+    } else if size.y >= size.z {
+        1
+    } else {
+        2
+    };
+    let mid = (start + end) / 2;
+    triangles[start..end].select_nth_unstable_by(mid - start, |a, b| {
+        let ca = a[0][axis] + a[1][axis] + a[2][axis];
+        let cb = b[0][axis] + b[1][axis] + b[2][axis];
+        ca.total_cmp(&cb)
+    });
+
+    // reserve the slot, children are filled in after recursing
+    nodes.push(Node::Leaf { bounds, start, end });
+    let left = build(nodes, triangles, start, mid);
+    let right = build(nodes, triangles, mid, end);
+    nodes[index] = Node::Inner {
+        bounds,
+        left,
+        right,
+    };
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::tests::{cuboid, unit_cube};
+
+    const TRIANGLE: [Vec3; 3] = [Vec3::ZERO, Vec3::X, Vec3::Y];
+
+    #[test]
+    fn closest_point_in_each_region() {
+        let cases = [
+            // above the face
+            (Vec3::new(0.25, 0.25, 1.0), Vec3::new(0.25, 0.25, 0.0)),
+            // beyond each vertex
+            (Vec3::new(-1.0, -1.0, 0.0), Vec3::ZERO),
+            (Vec3::new(2.0, -0.5, 0.0), Vec3::X),
+            (Vec3::new(-0.5, 2.0, 0.0), Vec3::Y),
+            // beyond each edge
+            (Vec3::new(0.5, -1.0, 0.0), Vec3::new(0.5, 0.0, 0.0)),
+            (Vec3::new(-1.0, 0.5, 0.0), Vec3::new(0.0, 0.5, 0.0)),
+            (Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.5, 0.5, 0.0)),
+        ];
+        for (p, expected) in cases {
+            let q = closest_point_on_triangle(p, TRIANGLE);
+            assert!(q.distance(expected) < 1e-6, "{p}: {q} != {expected}");
+        }
+    }
+
+    #[test]
+    fn closest_point_on_a_degenerate_triangle_is_a_vertex() {
+        let q = closest_point_on_triangle(Vec3::ONE, [Vec3::X; 3]);
+        assert_eq!(q, Vec3::X);
+    }
+
+    #[test]
+    fn ray_triangle_hits_inside_only() {
+        let down = Vec3::NEG_Z;
+        let (t, _) = ray_triangle(Vec3::new(0.2, 0.2, 2.0), down, TRIANGLE).unwrap();
+        assert!((t - 2.0).abs() < 1e-6);
+        assert!(ray_triangle(Vec3::new(0.8, 0.8, 2.0), down, TRIANGLE).is_none());
+        assert!(ray_triangle(Vec3::new(0.2, 0.2, -2.0), down, TRIANGLE).is_none());
+    }
+
+    #[test]
+    fn raycast_and_containment() {
+        let bvh = Bvh::new(&unit_cube());
+        let t = bvh.raycast(Vec3::new(0.5, 0.5, -1.0), Vec3::Z).unwrap();
+        assert!((t - 1.0).abs() < 1e-6);
+        assert!(bvh.raycast(Vec3::new(2.0, 2.0, -1.0), Vec3::Z).is_none());
+        assert_eq!(bvh.crossings(Vec3::new(0.3, 0.6, -1.0), Vec3::Z).len(), 2);
+
+        assert!(bvh.contains(Vec3::splat(0.5)));
+        assert!(bvh.contains(Vec3::new(0.1, 0.9, 0.2)));
+        assert!(!bvh.contains(Vec3::new(1.5, 0.5, 0.5)));
+        assert!(!bvh.contains(Vec3::new(-0.5, -0.5, -0.5)));
+    }
+
+    #[test]
+    fn ray_entry_of_a_box() {
+        let aabb = Aabb::from_points(&[Vec3::ZERO, Vec3::new(2.0, 1.0, 4.0)]);
+        let entry = aabb.ray_entry(Vec3::new(-1.0, 0.5, 2.0), Vec3::X.recip());
+        assert_eq!(entry, Some(1.0));
+        assert_eq!(
+            aabb.ray_entry(Vec3::new(-1.0, 5.0, 2.0), Vec3::X.recip()),
+            None
+        );
+        assert_eq!(aabb.ray_entry(Vec3::ONE, Vec3::X.recip()), Some(0.0));
+    }
+
+    #[test]
+    fn closest_point_matches_brute_force() {
+        let mesh = cuboid(Vec3::new(-1.0, 0.0, 2.0), Vec3::new(3.0, 0.5, 4.0));
+        let bvh = Bvh::new(&mesh);
+        for p in [
+            Vec3::ZERO,
+            Vec3::new(1.0, 0.25, 3.0),
+            Vec3::new(5.0, -2.0, 3.0),
+            Vec3::new(-3.0, 4.0, 10.0),
+        ] {
+            let brute = (0..mesh.triangles.len())
+                .map(|i| (p - closest_point_on_triangle(p, mesh.triangle(i))).length_squared())
+                .fold(f32::INFINITY, f32::min);
+            assert!((bvh.distance_squared(p) - brute).abs() < 1e-5, "{p}");
+        }
+    }
+
+    #[test]
+    fn distance_to_a_cube() {
+        let bvh = Bvh::new(&unit_cube());
+        assert!((bvh.distance_squared(Vec3::new(0.5, 0.5, 3.0)) - 4.0).abs() < 1e-5);
+        assert!((bvh.distance_squared(Vec3::new(0.5, 0.5, 0.25)) - 0.0625).abs() < 1e-5);
+        assert_eq!(
+            Bvh::new(&Mesh::default()).distance_squared(Vec3::ZERO),
+            f32::INFINITY
+        );
+    }
+
+    #[test]
+    fn aabb_queries() {
+        let aabb = Aabb::from_points(&[Vec3::ZERO, Vec3::new(2.0, 1.0, 4.0)]);
+        assert_eq!(aabb.center(), Vec3::new(1.0, 0.5, 2.0));
+        assert_eq!(aabb.distance_squared(Vec3::new(1.0, 0.5, 2.0)), 0.0);
+        assert_eq!(aabb.distance_squared(Vec3::new(3.0, 0.5, 6.0)), 5.0);
+        assert!(!aabb.is_empty());
+        assert!(!Aabb::from_points(&[Vec3::ONE]).is_empty());
+        assert!(Aabb::default().is_empty());
+        assert!(Aabb::from_points(&[Vec3::NAN]).is_empty());
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+
+use rerun::external::glam::{IVec3, Vec3};
+
+use crate::mesh::Mesh;
+
+/// Scanned points with optional per-point normals and colours, empty when absent
+#[derive(Clone, Debug, Default)]
+pub struct PointCloud {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub colors: Vec<[u8; 3]>,
+}
+
+impl From<PointCloud> for Mesh {
+    fn from(cloud: PointCloud) -> Self {
+        Self {
+            positions: cloud.positions,
+            triangles: vec![],
+        }
+    }
+}
+
+impl From<Mesh> for PointCloud {
+    fn from(mesh: Mesh) -> Self {
+        Self {
+            positions: mesh.positions,
+            ..Self::default()
+        }
+    }
+}
+
+impl PointCloud {
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn has_normals(&self) -> bool {
+        !self.is_empty() && self.normals.len() == self.len()
+    }
+
+    pub fn has_colors(&self) -> bool {
+        !self.is_empty() && self.colors.len() == self.len()
+    }
+
+    /// Only the points at `indices`, attributes included
+    pub fn select(&self, indices: &[usize]) -> Self {
+        Self {
+            positions: indices.iter().map(|&i| self.positions[i]).collect(),
+            normals: if self.has_normals() {
+                indices.iter().map(|&i| self.normals[i]).collect()
+            } else {
+                vec![]
+            },
+            colors: if self.has_colors() {
+                indices.iter().map(|&i| self.colors[i]).collect()
+            } else {
+                vec![]
+            },
+        }
+    }
+
+    /// One point per occupied cell of side `cell`: the centroid of its points,
+    /// with averaged normals and colours
+    pub fn voxel_downsample(&self, cell: f32) -> Self {
+        if cell <= 0.0 || self.is_empty() {
+            return self.clone();
+        }
+
+        let mut cells: HashMap<IVec3, Vec<usize>> = HashMap::new();
+        for (i, p) in self.positions.iter().enumerate() {
+            cells
+                .entry((*p / cell).floor().as_ivec3())
+                .or_default()
+                .push(i);
+        }
+        // hash order is arbitrary, keep the output stable between runs
+        let mut cells: Vec<Vec<usize>> = cells.into_values().collect();
+        cells.sort_unstable_by_key(|points| points[0]);
+
+        let mut out = Self::default();
+        for points in cells {
+            let n = points.len() as f32;
+            out.positions
+                .push(points.iter().map(|&i| self.positions[i]).sum::<Vec3>() / n);
+            if self.has_normals() {
+                let sum: Vec3 = points.iter().map(|&i| self.normals[i]).sum();
+                out.normals.push(sum.normalize_or_zero());
+            }
+            if self.has_colors() {
+                let mut sum = [0.0f32; 3];
+                for &i in &points {
+                    for (s, c) in sum.iter_mut().zip(self.colors[i]) {
+                        *s += c as f32;
+                    }
+                }
+                out.colors.push(sum.map(|s| (s / n).round() as u8));
+            }
+        }
+        out
+    }
+
+    /// At most `n` points, each as far as possible from the ones before it,
+    /// starting from the point closest to the centroid
+    pub fn farthest_point_downsample(&self, n: usize) -> Self {
+        if n >= self.len() {
+            return self.clone();
+        }
+
+        let centroid = self.positions.iter().sum::<Vec3>() / self.len() as f32;
+        let mut distances: Vec<f32> = self
+            .positions
+            .iter()
+            .map(|p| p.distance_squared(centroid))
+            .collect();
+        let mut next = argmin(&distances);
+        distances.fill(f32::INFINITY);
+
+        let mut picked = Vec::with_capacity(n);
+        while picked.len() < n {
+            picked.push(next);
+            let q = self.positions[next];
+            for (d, p) in distances.iter_mut().zip(&self.positions) {
+                *d = d.min(p.distance_squared(q));
+            }
+            next = argmax(&distances);
+        }
+        picked.sort_unstable();
+        self.select(&picked)
+    }
+
+    /// Drops points whose mean distance to their `k` nearest neighbours is more
+    /// than `std_ratio` standard deviations above the cloud's average
+    pub fn remove_outliers(&self, k: usize, std_ratio: f32) -> Self {
+        if k == 0 || self.len() <= k {
+            return self.clone();
+        }
+
+        let grid = Grid::new(&self.positions, k);
+        let means: Vec<f32> = self
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let nearest = grid.nearest(&self.positions, *p, k, i);
+                nearest.iter().map(|d| d.sqrt()).sum::<f32>() / nearest.len() as f32
+            })
+            .collect();
+
+        let n = means.len() as f32;
+        let mean = means.iter().sum::<f32>() / n;
+        let std = (means.iter().map(|m| (m - mean).powi(2)).sum::<f32>() / n).sqrt();
+        let limit = mean + std_ratio * std;
+
+        let kept: Vec<usize> = (0..self.len()).filter(|&i| means[i] <= limit).collect();
+        self.select(&kept)
+    }
+}
+
+fn argmin(values: &[f32]) -> usize {
+    (0..values.len())
+        .min_by(|&a, &b| values[a].total_cmp(&values[b]))
+        .unwrap_or_default()
+}
+
+fn argmax(values: &[f32]) -> usize {
+    (0..values.len())
+        .max_by(|&a, &b| values[a].total_cmp(&values[b]))
+        .unwrap_or_default()
+}
+
+/// Uniform hash grid sized for about `k` points per cell, for neighbour queries
+struct Grid {
+    cell: f32,
+    cells: HashMap<IVec3, Vec<usize>>,
+}
+
+impl Grid {
+    fn new(points: &[Vec3], k: usize) -> Self {
+        // extent of the bulk of the cloud, so the outliers we are after don't inflate it
+        let size = Vec3::from_array(std::array::from_fn(|axis| {
+            let mut values: Vec<f32> = points.iter().map(|p| p[axis]).collect();
+            values.sort_unstable_by(f32::total_cmp);
+            let at = |q: f32| values[((values.len() - 1) as f32 * q) as usize];
+            at(0.95) - at(0.05)
+        })) / 0.9;
+        // treat the cloud as a surface: points per unit area sets the spacing
+        let area = (size.x * size.y + size.y * size.z + size.z * size.x).max(f32::EPSILON);
+        let cell = (area * k as f32 / points.len() as f32).sqrt().max(1e-6);
+
+        let mut cells: HashMap<IVec3, Vec<usize>> = HashMap::new();
+        for (i, p) in points.iter().enumerate() {
+            cells.entry(Self::key(*p, cell)).or_default().push(i);
+        }
+        Self { cell, cells }
+    }
+
+    fn key(p: Vec3, cell: f32) -> IVec3 {
+        (p / cell).floor().as_ivec3()
+    }
+
+    /// Squared distances to the `k` nearest points other than `skip`, ascending
+    fn nearest(&self, points: &[Vec3], p: Vec3, k: usize, skip: usize) -> Vec<f32> {
+        const MAX_RING: i32 = 8;
+
+        let center = Self::key(p, self.cell);
+        let mut found: Vec<f32> = vec![];
+        for ring in 0..=MAX_RING {
+            for x in -ring..=ring {
+                for y in -ring..=ring {
+                    for z in -ring..=ring {
+                        if x.abs().max(y.abs()).max(z.abs()) != ring {
+                            continue;
+                        }
+                        let Some(cell) = self.cells.get(&(center + IVec3::new(x, y, z))) else {
+                            continue;
+                        };
+                        found.extend(
+                            cell.iter()
+                                .filter(|&&i| i != skip)
+                                .map(|&i| points[i].distance_squared(p)),
+                        );
+                    }
+                }
+            }
+
+            // everything within `ring` cells of p has been seen
+            let reach = ring as f32 * self.cell;
+            found.sort_unstable_by(f32::total_cmp);
+            if found.len() >= k && found[k - 1] <= reach * reach {
+                found.truncate(k);
+                return found;
+            }
+        }
+
+        // far from everything, e.g. an outlier: just look at every point
+        found = (0..points.len())
+            .filter(|&i| i != skip)
+            .map(|i| points[i].distance_squared(p))
+            .collect();
+        found.sort_unstable_by(f32::total_cmp);
+        found.truncate(k);
+        found
+    }
+}
+
+/// How raw point cloud targets are thinned before the search sees them
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct CloudFilter {
+    /// drop points whose mean neighbour distance is this many standard
+    /// deviations above average
+    #[arg(long)]
+    pub outliers: Option<f32>,
+    /// neighbours considered by --outliers
+    #[arg(long, default_value_t = 8)]
+    pub neighbours: usize,
+    /// merge points into voxels of this size
+    #[arg(long)]
+    pub voxel: Option<f32>,
+    /// cap the point count with farthest point sampling
+    #[arg(long)]
+    pub max_points: Option<usize>,
+}
+
+impl CloudFilter {
+    /// Outliers first so they can't survive as far points, then voxels, then the cap
+    pub fn apply(&self, cloud: &PointCloud) -> PointCloud {
+        let mut cloud = cloud.clone();
+        if let Some(std_ratio) = self.outliers {
+            cloud = cloud.remove_outliers(self.neighbours, std_ratio);
+        }
+        if let Some(cell) = self.voxel {
+            cloud = cloud.voxel_downsample(cell);
+        }
+        if let Some(n) = self.max_points {
+            cloud = cloud.farthest_point_downsample(n);
+        }
+        cloud
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `n` by `n` points one apart in the xy plane
+    fn plane(n: usize) -> Vec<Vec3> {
+        (0..n * n)
+            .map(|i| Vec3::new((i % n) as f32, (i / n) as f32, 0.0))
+            .collect()
+    }
+
+    #[test]
+    fn voxels_average_their_points() {
+        let cloud = PointCloud {
+            positions: vec![Vec3::splat(0.1), Vec3::new(1.5, 0.0, 0.0), Vec3::splat(0.3)],
+            normals: vec![Vec3::X, Vec3::Z, Vec3::Y],
+            colors: vec![[0, 0, 0], [1, 2, 3], [255, 255, 255]],
+        };
+        let out = cloud.voxel_downsample(1.0);
+        assert_eq!(out.len(), 2);
+        assert!(out.positions[0].distance(Vec3::splat(0.2)) < 1e-6);
+        assert_eq!(out.positions[1], Vec3::new(1.5, 0.0, 0.0));
+        assert!(out.normals[0].distance(Vec3::new(1.0, 1.0, 0.0).normalize()) < 1e-6);
+        assert_eq!(out.colors, vec![[128, 128, 128], [1, 2, 3]]);
+        assert_eq!(cloud.voxel_downsample(0.0).len(), 3);
+    }
+
+    #[test]
+    fn farthest_points_start_at_the_centre() {
+        let cloud = PointCloud {
+            positions: (0..=10).map(|x| Vec3::new(x as f32, 0.0, 0.0)).collect(),
+            ..PointCloud::default()
+        };
+        let xs: Vec<f32> = cloud
+            .farthest_point_downsample(3)
+            .positions
+            .iter()
+            .map(|p| p.x)
+            .collect();
+        assert_eq!(xs, vec![0.0, 5.0, 10.0]);
+        assert_eq!(cloud.farthest_point_downsample(20).len(), 11);
+    }
+
+    #[test]
+    fn outliers_are_dropped_and_the_surface_kept() {
+        let mut positions = plane(5);
+        positions.push(Vec3::new(2.0, 2.0, 100.0));
+        let cloud = PointCloud {
+            positions,
+            ..PointCloud::default()
+        };
+        assert_eq!(cloud.remove_outliers(4, 2.0).positions, plane(5));
+        assert_eq!(cloud.remove_outliers(0, 2.0).len(), 26);
+    }
+
+    #[test]
+    fn neighbours_match_brute_force() {
+        let points = plane(6);
+        let grid = Grid::new(&points, 4);
+        for (i, p) in points.iter().enumerate() {
+            let mut expected: Vec<f32> = (0..points.len())
+                .filter(|&j| j != i)
+                .map(|j| points[j].distance_squared(*p))
+                .collect();
+            expected.sort_unstable_by(f32::total_cmp);
+            expected.truncate(4);
+            assert_eq!(grid.nearest(&points, *p, 4, i), expected);
+        }
+    }
+}
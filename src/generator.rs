@@ -0,0 +1,203 @@
+use std::fmt;
+
+use anyhow::anyhow;
+use rand::{distr::weighted::WeightedIndex, prelude::*};
+
+use crate::{
+    bvh::Aabb,
+    mesh::{params_to_mesh, Mesh},
+    metric::surface_distance,
+    optimize::ParamBounds,
+};
+
+/// Programs drawn before giving up on finding a valid one
+const ATTEMPTS: usize = 100;
+
+/// `low..high`, as taken by the range flags
+fn parse_range(s: &str) -> anyhow::Result<(f32, f32)> {
+    let (low, high) = s
+        .split_once("..")
+        .ok_or_else(|| anyhow!("expected low..high: {s}"))?;
+    let (low, high): (f32, f32) = (low.trim().parse()?, high.trim().parse()?);
+    if low > high {
+        Err(anyhow!("empty range: {s}"))?
+    }
+    Ok((low, high))
+}
+
+/// Shape of the random programs used as targets and benchmarks
+#[derive(clap::Args, Clone, Debug, PartialEq)]
+pub struct GeneratorConfig {
+    /// fewest primitives in a program
+    #[arg(long, default_value_t = 2)]
+    pub min_primitives: usize,
+    /// most primitives in a program
+    #[arg(long, default_value_t = 2)]
+    pub max_primitives: usize,
+    /// relative odds of cube, sphere and cylinder
+    #[arg(long, value_delimiter = ',', default_value = "1,1,1")]
+    pub kind_weights: Vec<f32>,
+    #[arg(long, value_parser = parse_range, default_value = "1..20")]
+    pub size_range: (f32, f32),
+    #[arg(long, value_parser = parse_range, default_value = "0..5")]
+    pub translation_range: (f32, f32),
+    #[arg(long, value_parser = parse_range, default_value = "0..360")]
+    pub rotation_range: (f32, f32),
+    /// chance that a primitive is intersected with, rather than added to, the ones before
+    #[arg(long, default_value_t = 0.0)]
+    pub intersections: f32,
+    /// longest chain of intersections, which µcad binds tighter than unions,
+    /// so the depth of the CSG tree under the top level union
+    #[arg(long, default_value_t = 2)]
+    pub max_depth: usize,
+    /// keep programs that render empty, disconnected or with useless primitives
+    #[arg(long)]
+    pub allow_degenerate: bool,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        let bounds = ParamBounds::default();
+        Self {
+            min_primitives: 2,
+            max_primitives: 2,
+            kind_weights: vec![1.0; 3],
+            size_range: bounds.size,
+            translation_range: bounds.translation,
+            rotation_range: bounds.rotation,
+            intersections: 0.0,
+            max_depth: 2,
+            allow_degenerate: false,
+        }
+    }
+}
+
+impl GeneratorConfig {
+    pub fn bounds(&self) -> ParamBounds {
+        ParamBounds {
+            size: self.size_range,
+            translation: self.translation_range,
+            rotation: self.rotation_range,
+        }
+    }
+
+    /// One primitive, combined with the ones before it by union
+    pub fn primitive(&self, rng: &mut impl Rng) -> (u8, [f32; 10]) {
+        let kind = match WeightedIndex::new(&self.kind_weights) {
+            Ok(weights) => weights.sample(rng) as u8,
+            Err(_) => rng.random_range(0..=2),
+        };
+        let mut range = |(low, high): (f32, f32)| rng.random_range(low..=high);
+
+        let mut params = [0f32; 10];
+        for p in params.iter_mut().take(3) {
+            *p = range(self.size_range);
+        }
+        for p in params.iter_mut().skip(3).take(3) {
+            *p = range(self.translation_range);
+        }
+        for p in params.iter_mut().skip(6).take(3) {
+            *p = range(self.rotation_range);
+        }
+        params[9] = 0f32;
+
+        (kind, params)
+    }
+
+    /// A program of `min_primitives..=max_primitives` primitives, degenerate or not
+    pub fn program(&self, rng: &mut impl Rng) -> (Vec<u8>, Vec<f32>) {
+        let min = self.min_primitives.max(1);
+        let count = rng.random_range(min..=self.max_primitives.max(min));
+        let mut kinds = Vec::with_capacity(count);
+        let mut params = Vec::with_capacity(count * 10);
+        let mut depth = 0;
+        for i in 0..count {
+            let (kind, mut p) = self.primitive(rng);
+            // the first primitive has nothing to intersect with
+            if i > 0 && depth < self.max_depth && rng.random::<f32>() < self.intersections {
+                p[9] = 2.0;
+                depth += 1;
+            } else {
+                depth = 0;
+            }
+            kinds.push(kind);
+            params.extend(p);
+        }
+        (kinds, params)
+    }
+
+    /// Draws programs until one is valid, see [`check`]
+    pub fn valid_program(&self, rng: &mut impl Rng) -> anyhow::Result<(Vec<u8>, Vec<f32>)> {
+        let mut last = None;
+        for _ in 0..ATTEMPTS {
+            let (kinds, params) = self.program(rng);
+            if self.allow_degenerate {
+                return Ok((kinds, params));
+            }
+            match check(&kinds, &params) {
+                Ok(()) => return Ok((kinds, params)),
+                Err(e) => last = Some(e),
+            }
+        }
+        Err(anyhow!(
+            "no valid program in {ATTEMPTS} attempts, last was {}",
+            last.map_or("?".into(), |e| e.to_string())
+        ))
+    }
+}
+
+/// Why a program makes a poor target
+#[derive(Clone, Debug, PartialEq)]
+pub enum Degenerate {
+    Empty,
+    /// pieces that don't touch
+    Disconnected(usize),
+    /// removing this primitive leaves the shape unchanged
+    Redundant(usize),
+}
+
+impl fmt::Display for Degenerate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Degenerate::Empty => write!(f, "empty"),
+            Degenerate::Disconnected(n) => write!(f, "{n} disconnected pieces"),
+            Degenerate::Redundant(i) => write!(f, "primitive {i} contributes nothing"),
+        }
+    }
+}
+
+impl std::error::Error for Degenerate {}
+
+/// Renders the program and each program with one primitive left out
+pub fn check(kinds: &[u8], params: &[f32]) -> Result<(), Degenerate> {
+    let mesh = match params_to_mesh(kinds, params) {
+        Ok(mesh) if !mesh.triangles.is_empty() => mesh,
+        _ => return Err(Degenerate::Empty),
+    };
+    let components = mesh.components();
+    if components > 1 {
+        return Err(Degenerate::Disconnected(components));
+    }
+
+    // a lone primitive has nothing to be redundant with
+    if kinds.len() == 1 {
+        return Ok(());
+    }
+    let diagonal = Aabb::from_points(&mesh.positions).size().length();
+    let tolerance = (diagonal * 1e-3).powi(2);
+    for i in 0..kinds.len() {
+        let mut k = kinds.to_vec();
+        let mut p = params.to_vec();
+        k.remove(i);
+        p.drain(i * 10..(i + 1) * 10);
+        let without = params_to_mesh(&k, &p).unwrap_or_default();
+        if same_shape(&mesh, &without, tolerance) {
+            return Err(Degenerate::Redundant(i));
+        }
+    }
+    Ok(())
+}
+
+fn same_shape(a: &Mesh, b: &Mesh, tolerance: f32) -> bool {
+    !b.triangles.is_empty() && surface_distance(a, b) <= tolerance
+}
@@ -0,0 +1,499 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::anyhow;
+use itertools::iproduct;
+use serde::{Deserialize, Serialize};
+
+use crate::{bvh::Aabb, mesh::Mesh, optimize::ParamBounds};
+
+/// Values per axis when a range doesn't give a count
+pub const DEFAULT_STEPS: usize = 3;
+
+/// `steps` evenly spaced values of one parameter, both ends included
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "SavedAxis")]
+pub struct Axis {
+    pub low: f32,
+    pub high: f32,
+    pub steps: usize,
+}
+
+impl Axis {
+    pub fn new(low: f32, high: f32, steps: usize) -> Self {
+        Self { low, high, steps }
+    }
+
+    /// A single step sits in the middle of the range
+    pub fn value(&self, i: usize) -> f32 {
+        match self.steps {
+            0 | 1 => (self.low + self.high) * 0.5,
+            n => self.low + (self.high - self.low) * i as f32 / (n - 1) as f32,
+        }
+    }
+
+    /// The point a fraction `t` of the way from low to high
+    pub fn at(&self, t: f32) -> f32 {
+        self.low + (self.high - self.low) * t
+    }
+
+    pub fn values(&self) -> Vec<f32> {
+        (0..self.steps).map(|i| self.value(i)).collect()
+    }
+
+    /// Distance between neighbouring values
+    pub fn spacing(&self) -> f32 {
+        match self.steps {
+            0 | 1 => 0.0,
+            n => (self.high - self.low) / (n - 1) as f32,
+        }
+    }
+
+    /// The axis if its bounds are finite and in order and it has a step
+    fn checked(self) -> anyhow::Result<Self> {
+        if !self.low.is_finite() || !self.high.is_finite() {
+            Err(anyhow!("bounds must be finite"))?
+        }
+        if self.low > self.high {
+            Err(anyhow!("empty range"))?
+        }
+        if self.steps == 0 {
+            Err(anyhow!("no steps"))?
+        }
+        Ok(self)
+    }
+}
+
+/// An [`Axis`] as read from a saved session, before it is checked
+#[derive(Deserialize)]
+struct SavedAxis {
+    low: f32,
+    high: f32,
+    steps: usize,
+}
+
+impl TryFrom<SavedAxis> for Axis {
+    type Error = anyhow::Error;
+
+    fn try_from(saved: SavedAxis) -> anyhow::Result<Self> {
+        Axis::new(saved.low, saved.high, saved.steps).checked()
+    }
+}
+
+/// `low..high`, `low..high:steps` or a single fixed value
+impl FromStr for Axis {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let number = |t: &str| {
+            t.trim()
+                .parse::<f32>()
+                .map_err(|_| anyhow!("invalid number `{t}` in {s}"))
+        };
+        let (range, steps) = match s.split_once(':') {
+            Some((range, steps)) => (
+                range,
+                steps
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("invalid step count in {s}"))?,
+            ),
+            None => (s, DEFAULT_STEPS),
+        };
+        let axis = match range.split_once("..") {
+            Some((low, high)) => Axis::new(number(low)?, number(high)?, steps),
+            None => {
+                let value = number(range)?;
+                Axis::new(value, value, 1)
+            }
+        };
+        axis.checked().map_err(|e| anyhow!("{e}: {s}"))
+    }
+}
+
+impl fmt::Display for Axis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.low == self.high {
+            true => write!(f, "{}", self.low),
+            false => write!(f, "{}..{}:{}", self.low, self.high, self.steps),
+        }
+    }
+}
+
+/// One range for all three axes, or one per axis
+pub fn axes(ranges: &[&str]) -> anyhow::Result<[Axis; 3]> {
+    match ranges {
+        [all] => Ok([all.parse()?; 3]),
+        [x, y, z] => Ok([x.parse()?, y.parse()?, z.parse()?]),
+        _ => Err(anyhow!("expected one range or three, one per axis"))?,
+    }
+}
+
+/// [`axes`] separated by commas, as taken by the range flags
+pub fn parse_axes(s: &str) -> anyhow::Result<[Axis; 3]> {
+    axes(&s.split(',').collect::<Vec<_>>())
+}
+
+/// Values tried for the nine continuous parameters of a primitive; the
+/// operation isn't searched
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Grid {
+    pub size: [Axis; 3],
+    pub translation: [Axis; 3],
+    pub rotation: [Axis; 3],
+}
+
+impl Grid {
+    /// Sizes from a tenth of the target's longest side to all of it,
+    /// translations across its bounding box and rotations in quarter turns
+    pub fn fit(target: &Mesh) -> Self {
+        if target.positions.is_empty() {
+            let bounds = ParamBounds::default();
+            let axis = |(low, high)| [Axis::new(low, high, DEFAULT_STEPS); 3];
+            return Self {
+                size: axis(bounds.size),
+                translation: axis(bounds.translation),
+                rotation: [Axis::new(0.0, 270.0, 4); 3],
+            };
+        }
+        let aabb = Aabb::from_points(&target.positions);
+        let longest = aabb.size().max_element().max(f32::EPSILON);
+        Self {
+            size: [Axis::new(longest * 0.1, longest, DEFAULT_STEPS); 3],
+            translation: std::array::from_fn(|i| {
+                Axis::new(aabb.min[i], aabb.max[i], DEFAULT_STEPS)
+            }),
+            rotation: [Axis::new(0.0, 270.0, 4); 3],
+        }
+    }
+
+    /// In parameter order, sx to rz
+    pub fn axes(&self) -> [Axis; 9] {
+        let [s, t, r] = [self.size, self.translation, self.rotation];
+        [s[0], s[1], s[2], t[0], t[1], t[2], r[0], r[1], r[2]]
+    }
+
+    /// Candidates per primitive kind
+    pub fn len(&self) -> usize {
+        self.axes().iter().map(|axis| axis.steps.max(1)).product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Parameters of every candidate, with the union operation
+    pub fn candidates(&self) -> Vec<[f32; 10]> {
+        let [sx, sy, sz, tx, ty, tz, rx, ry, rz] = self.axes().map(|axis| axis.values());
+        iproduct!(sx, sy, sz, tx, ty, tz, rx, ry, rz)
+            .map(|(sx, sy, sz, tx, ty, tz, rx, ry, rz)| [sx, sy, sz, tx, ty, tz, rx, ry, rz, 0.0])
+            .collect()
+    }
+
+    /// Parameters at fractions `unit` of every axis' range, with the union
+    /// operation
+    pub fn point(&self, unit: [f32; 9]) -> [f32; 10] {
+        let axes = self.axes();
+        let mut params = [0.0; 10];
+        for (param, (axis, t)) in params.iter_mut().zip(axes.iter().zip(unit)) {
+            *param = axis.at(t);
+        }
+        params
+    }
+
+    /// A grid with as many steps over the cell of `params`, half a spacing
+    /// either side of it on every axis and never outside this grid
+    pub fn around(&self, params: &[f32; 10]) -> Self {
+        let axes = self.axes();
+        let axis = |i: usize| {
+            let axis = axes[i];
+            let half = axis.spacing() * 0.5;
+            let low = (params[i] - half).max(axis.low);
+            let high = (params[i] + half).min(axis.high);
+            Axis::new(low, high.max(low), axis.steps)
+        };
+        Self {
+            size: std::array::from_fn(axis),
+            translation: std::array::from_fn(|i| axis(3 + i)),
+            rotation: std::array::from_fn(|i| axis(6 + i)),
+        }
+    }
+
+    /// Finest spacing of the size and translation axes that take more than
+    /// one value, 0 when none do
+    pub fn resolution(&self) -> f32 {
+        self.size
+            .iter()
+            .chain(&self.translation)
+            .map(Axis::spacing)
+            .filter(|spacing| *spacing > 0.0)
+            .reduce(f32::min)
+            .unwrap_or(0.0)
+    }
+
+    /// The smallest box of the optimizer's kind holding every axis
+    pub fn bounds(&self) -> ParamBounds {
+        let span = |axes: &[Axis; 3]| {
+            axes.iter()
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), axis| {
+                    (low.min(axis.low), high.max(axis.high))
+                })
+        };
+        ParamBounds {
+            size: span(&self.size),
+            translation: span(&self.translation),
+            rotation: span(&self.rotation),
+        }
+    }
+}
+
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let axes = |axes: &[Axis; 3]| match axes.iter().all(|axis| *axis == axes[0]) {
+            true => axes[0].to_string(),
+            false => format!("{},{},{}", axes[0], axes[1], axes[2]),
+        };
+        write!(
+            f,
+            "size {}, translation {}, rotation {} ({} candidates per kind)",
+            axes(&self.size),
+            axes(&self.translation),
+            axes(&self.rotation),
+            self.len()
+        )
+    }
+}
+
+/// Grid ranges, each defaulting to [`Grid::fit`] of the target
+#[derive(clap::Args, Clone, Debug, Default, PartialEq)]
+pub struct GridConfig {
+    /// sizes searched, `low..high:steps` for all axes or three of them
+    /// separated by commas
+    #[arg(long = "search-size", value_parser = parse_axes)]
+    pub size: Option<[Axis; 3]>,
+    /// translations searched, like `--search-size`
+    #[arg(long = "search-translation", value_parser = parse_axes)]
+    pub translation: Option<[Axis; 3]>,
+    /// rotations searched in degrees, like `--search-size`
+    #[arg(long = "search-rotation", value_parser = parse_axes)]
+    pub rotation: Option<[Axis; 3]>,
+}
+
+impl GridConfig {
+    pub fn grid(&self, target: &Mesh) -> Grid {
+        let fit = Grid::fit(target);
+        Grid {
+            size: self.size.unwrap_or(fit.size),
+            translation: self.translation.unwrap_or(fit.translation),
+            rotation: self.rotation.unwrap_or(fit.rotation),
+        }
+    }
+}
+
+/// Coarse-to-fine search: the best cells of a grid are searched again with
+/// a finer grid of their own until the score stops improving
+#[derive(clap::Args, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Refine {
+    /// re-grid around the best candidates at most this many times, 0 searches
+    /// the grid once
+    #[arg(long = "refine", value_name = "LEVELS", default_value_t = 0)]
+    pub levels: usize,
+    /// cells refined at each level, more of them keep other basins alive
+    #[arg(long = "refine-keep", value_name = "CELLS", default_value_t = 1)]
+    pub keep: usize,
+    /// stop once a level improves the best score by less than this fraction
+    #[arg(
+        long = "refine-tolerance",
+        value_name = "FRACTION",
+        default_value_t = 0.01
+    )]
+    pub tolerance: f32,
+}
+
+impl Default for Refine {
+    fn default() -> Self {
+        Self {
+            levels: 0,
+            keep: 1,
+            tolerance: 0.01,
+        }
+    }
+}
+
+impl Refine {
+    /// True when a level taking the best score from `previous` to `best`
+    /// isn't worth another one
+    pub fn stalled(&self, previous: f32, best: f32) -> bool {
+        previous - best <= self.tolerance * previous.abs()
+    }
+}
+
+impl fmt::Display for Refine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.levels {
+            0 => write!(f, "off"),
+            levels => write!(
+                f,
+                "up to {levels} levels, {} cells each, until the score improves by less than {}",
+                self.keep, self.tolerance
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::tests::cuboid;
+    use rerun::external::glam::Vec3;
+
+    #[test]
+    fn axes_parse_ranges_steps_and_values() {
+        assert_eq!(
+            "1..20".parse::<Axis>().unwrap(),
+            Axis::new(1.0, 20.0, DEFAULT_STEPS)
+        );
+        assert_eq!(
+            "-1.5 .. 2:5".parse::<Axis>().unwrap(),
+            Axis::new(-1.5, 2.0, 5)
+        );
+        assert_eq!("4".parse::<Axis>().unwrap(), Axis::new(4.0, 4.0, 1));
+        assert_eq!("2..2:3".parse::<Axis>().unwrap(), Axis::new(2.0, 2.0, 3));
+        for bad in ["", "a..2", "1..b", "2..1", "1..2:0", "1..2:x", "1..2:-1"] {
+            assert!(bad.parse::<Axis>().is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn axes_need_finite_bounds() {
+        for bad in ["NaN", "nan..1", "0..NaN:3", "-inf..0", "0..inf:2"] {
+            assert!(bad.parse::<Axis>().is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn saved_axes_are_checked() {
+        let axis = Axis::new(-1.0, 2.0, 4);
+        let json = serde_json::to_string(&axis).unwrap();
+        assert_eq!(serde_json::from_str::<Axis>(&json).unwrap(), axis);
+        for bad in [
+            r#"{"low": 0, "high": 1, "steps": 0}"#,
+            r#"{"low": 1, "high": 0, "steps": 3}"#,
+            r#"{"low": null, "high": 1, "steps": 3}"#,
+        ] {
+            assert!(serde_json::from_str::<Axis>(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn axes_print_as_they_parse() {
+        for text in ["1..20:4", "-0.5..0.5:2", "3"] {
+            assert_eq!(text.parse::<Axis>().unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn values_include_both_ends() {
+        let axis = Axis::new(0.0, 90.0, 4);
+        assert_eq!(axis.values(), vec![0.0, 30.0, 60.0, 90.0]);
+        assert_eq!(axis.spacing(), 30.0);
+        let single = Axis::new(1.0, 3.0, 1);
+        assert_eq!(single.values(), vec![2.0]);
+        assert_eq!(single.spacing(), 0.0);
+    }
+
+    #[test]
+    fn one_range_or_three() {
+        assert_eq!(axes(&["1..2:2"]).unwrap(), [Axis::new(1.0, 2.0, 2); 3]);
+        let [x, y, z] = parse_axes("1,2..3:2,4").unwrap();
+        assert_eq!((x.low, y.high, z.low), (1.0, 3.0, 4.0));
+        assert!(axes(&["1", "2"]).is_err());
+        assert!(parse_axes("1,,2").is_err());
+    }
+
+    #[test]
+    fn fitted_grids_cover_the_target() {
+        let grid = Grid::fit(&cuboid(Vec3::new(-1.0, 0.0, 2.0), Vec3::new(3.0, 1.0, 4.0)));
+        assert_eq!(grid.size[0], Axis::new(0.4, 4.0, DEFAULT_STEPS));
+        assert_eq!(grid.translation[1], Axis::new(0.0, 1.0, DEFAULT_STEPS));
+        assert_eq!(grid.len(), 3usize.pow(6) * 4usize.pow(3));
+        let bounds = grid.bounds();
+        assert_eq!(bounds.translation, (-1.0, 4.0));
+        assert_eq!(bounds.rotation, (0.0, 270.0));
+
+        let empty = Grid::fit(&Mesh::default());
+        assert_eq!(empty.bounds().size, ParamBounds::default().size);
+    }
+
+    #[test]
+    fn flags_override_the_fit() {
+        let target = cuboid(Vec3::ZERO, Vec3::ONE);
+        let config = GridConfig {
+            rotation: Some([Axis::new(0.0, 0.0, 1); 3]),
+            ..GridConfig::default()
+        };
+        let grid = config.grid(&target);
+        assert_eq!(grid.size, Grid::fit(&target).size);
+        assert_eq!(grid.rotation, [Axis::new(0.0, 0.0, 1); 3]);
+        assert_eq!(grid.len(), 3usize.pow(6));
+    }
+
+    #[test]
+    fn candidates_are_every_combination() {
+        let mut grid = Grid::fit(&Mesh::default());
+        grid.size = [Axis::new(1.0, 2.0, 2); 3];
+        grid.translation = [Axis::new(0.0, 0.0, 1); 3];
+        grid.rotation = [
+            Axis::new(0.0, 90.0, 2),
+            Axis::new(0.0, 0.0, 1),
+            Axis::new(0.0, 0.0, 1),
+        ];
+        let candidates = grid.candidates();
+        assert_eq!(candidates.len(), grid.len());
+        assert_eq!(candidates.len(), 16);
+        assert_eq!(
+            candidates[0],
+            [1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            candidates[15],
+            [2.0, 2.0, 2.0, 0.0, 0.0, 0.0, 90.0, 0.0, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn refined_cells_stay_inside_the_grid() {
+        let grid = Grid {
+            size: [Axis::new(1.0, 5.0, 5); 3],
+            translation: [Axis::new(-2.0, 2.0, 3); 3],
+            rotation: [Axis::new(0.0, 0.0, 1); 3],
+        };
+        let best = [2.0, 1.0, 5.0, 0.0, -2.0, 2.0, 0.0, 0.0, 0.0, 0.0];
+        let cell = grid.around(&best);
+        assert_eq!(cell.size[0], Axis::new(1.5, 2.5, 5));
+        // clamped at the edges of the grid
+        assert_eq!(cell.size[1], Axis::new(1.0, 1.5, 5));
+        assert_eq!(cell.size[2], Axis::new(4.5, 5.0, 5));
+        assert_eq!(cell.translation[0], Axis::new(-1.0, 1.0, 3));
+        assert_eq!(cell.translation[1], Axis::new(-2.0, -1.0, 3));
+        assert_eq!(cell.rotation[0], Axis::new(0.0, 0.0, 1));
+        assert_eq!(cell.len(), grid.len());
+        // finer than the translations, and the fixed rotations don't count
+        assert_eq!(grid.resolution(), 1.0);
+        // the clamped size axis is finer still
+        assert_eq!(cell.resolution(), 0.125);
+    }
+
+    #[test]
+    fn refinement_stalls_below_the_tolerance() {
+        let refine = Refine {
+            levels: 3,
+            keep: 2,
+            tolerance: 0.1,
+        };
+        assert!(!refine.stalled(1.0, 0.8));
+        assert!(refine.stalled(1.0, 0.95));
+        assert!(refine.stalled(1.0, 1.2));
+        assert!(Refine::default().stalled(0.0, 0.0));
+        assert_eq!(Refine::default().to_string(), "off");
+    }
+}
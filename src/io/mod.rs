@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::{
+    cloud::{CloudFilter, PointCloud},
+    mesh::Mesh,
+    microcad::Microcad,
+};
+
+pub mod obj;
+pub mod pcd;
+pub mod ply;
+pub mod stl;
+pub mod xyz;
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default()
+}
+
+/// Reads a mesh from an STL (ASCII or binary), OBJ or PLY file by extension
+pub fn load_mesh(path: impl AsRef<Path>) -> anyhow::Result<Mesh> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+
+    let mesh = match extension(path).as_str() {
+        "stl" => stl::parse(&bytes)?,
+        "obj" => obj::parse(std::str::from_utf8(&bytes)?)?,
+        "ply" => ply::parse(&bytes)?,
+        _ => Err(anyhow!("unsupported mesh format: {}", path.display()))?,
+    };
+    if mesh.is_empty() {
+        Err(anyhow!("no vertices in {}", path.display()))?
+    }
+    Ok(mesh)
+}
+
+/// Writes a mesh as binary STL, OBJ or ASCII PLY by extension
+pub fn save_mesh(path: impl AsRef<Path>, mesh: &Mesh) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let bytes = match extension(path).as_str() {
+        "stl" => stl::write(mesh),
+        "obj" => obj::write(mesh).into_bytes(),
+        "ply" => ply::write(mesh).into_bytes(),
+        _ => Err(anyhow!("unsupported mesh format: {}", path.display()))?,
+    };
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// Reads points from an XYZ, PCD or PLY file by extension, meshes and
+/// µcad programs give their vertices
+pub fn load_cloud(path: impl AsRef<Path>) -> anyhow::Result<PointCloud> {
+    let path = path.as_ref();
+    let cloud = match extension(path).as_str() {
+        "xyz" | "pts" | "txt" | "csv" => xyz::parse(&std::fs::read_to_string(path)?)?,
+        "pcd" => pcd::parse(&std::fs::read(path)?)?,
+        "ply" => ply::parse_cloud(&std::fs::read(path)?)?,
+        "µcad" | "ucad" | "mcad" => load_ucad(path)?.into(),
+        _ => load_mesh(path)?.into(),
+    };
+    if cloud.is_empty() {
+        Err(anyhow!("no points in {}", path.display()))?
+    }
+    Ok(cloud)
+}
+
+/// Renders a µcad program, resolving its `use`s next to it and in the library paths
+pub fn load_ucad(path: impl AsRef<Path>) -> anyhow::Result<Mesh> {
+    let path = path.as_ref();
+    let mut microcad = Microcad::new();
+    microcad.load_file(path)?;
+    let mesh: Mesh = microcad.render_mesh()?.into();
+    if mesh.is_empty() {
+        Err(anyhow!("{} renders to nothing", path.display()))?
+    }
+    Ok(mesh)
+}
+
+/// Meshes and µcad programs are kept whole, point clouds (including PLY without faces) go
+/// through `filter` and become meshes without triangles
+pub fn load_target(path: impl AsRef<Path>, filter: &CloudFilter) -> anyhow::Result<Mesh> {
+    let path = path.as_ref();
+    let cloud = match extension(path).as_str() {
+        "stl" | "obj" => return load_mesh(path),
+        "µcad" | "ucad" | "mcad" => return load_ucad(path),
+        "ply" => {
+            let ply = ply::Ply::parse(&std::fs::read(path)?)?;
+            if ply.has_faces() {
+                return ply.mesh();
+            }
+            ply.cloud()?
+        }
+        _ => load_cloud(path)?,
+    };
+
+    let cloud = filter.apply(&cloud);
+    if cloud.is_empty() {
+        Err(anyhow!(
+            "no points left in {} after filtering",
+            path.display()
+        ))?
+    }
+    Ok(cloud.into())
+}
@@ -0,0 +1,98 @@
+use anyhow::anyhow;
+use rerun::external::glam;
+
+use crate::mesh::Mesh;
+
+/// Vertices and faces of a Wavefront OBJ, faces are fanned into triangles
+pub fn parse(text: &str) -> anyhow::Result<Mesh> {
+    let mut mesh = Mesh::default();
+    for (n, line) in text.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let coords = words
+                    .take(3)
+                    .map(str::parse)
+                    .collect::<Result<Vec<f32>, _>>()?;
+                let [x, y, z] = coords[..] else {
+                    Err(anyhow!("line {}: vertex needs 3 coordinates", n + 1))?
+                };
+                mesh.positions.push(glam::vec3(x, y, z));
+            }
+            Some("f") => {
+                let face = words
+                    .map(|w| vertex_index(w, mesh.positions.len()))
+                    .collect::<anyhow::Result<Vec<u32>>>()?;
+                for i in 1..face.len().saturating_sub(1) {
+                    mesh.triangles.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(mesh)
+}
+
+/// `v`, `v/vt`, `v//vn` or `v/vt/vn`, one based, negative counts from the end
+fn vertex_index(word: &str, count: usize) -> anyhow::Result<u32> {
+    let v: i64 = word.split('/').next().unwrap_or_default().parse()?;
+    let index = if v < 0 { count as i64 + v } else { v - 1 };
+    if !(0..count as i64).contains(&index) {
+        Err(anyhow!("face index out of range: {word}"))?
+    }
+    Ok(index as u32)
+}
+
+pub fn write(mesh: &Mesh) -> String {
+    let mut text = String::new();
+    for p in &mesh.positions {
+        text.push_str(&format!("v {} {} {}\n", p.x, p.y, p.z));
+    }
+    for [a, b, c] in &mesh.triangles {
+        text.push_str(&format!("f {} {} {}\n", a + 1, b + 1, c + 1));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    #[test]
+    fn faces_are_fanned_and_indices_resolved() {
+        let text = "# a quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1 4//1\nf -4 -2 -1\n";
+        let mesh = parse(text).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn vertex_index_forms() {
+        assert_eq!(vertex_index("3", 4).unwrap(), 2);
+        assert_eq!(vertex_index("3/7", 4).unwrap(), 2);
+        assert_eq!(vertex_index("3/7/1", 4).unwrap(), 2);
+        assert_eq!(vertex_index("-1", 4).unwrap(), 3);
+        assert!(vertex_index("5", 4).is_err());
+        assert!(vertex_index("0", 4).is_err());
+        assert!(vertex_index("x", 4).is_err());
+    }
+
+    #[test]
+    fn rejects_short_vertices() {
+        assert!(parse("v 1 2\n").is_err());
+    }
+
+    #[test]
+    fn written_meshes_read_back() {
+        let mesh =
+            crate::mesh::tests::cuboid(Vec3::new(-1.5, 0.001, 2.0), Vec3::new(3.125, 0.25, 7.0));
+        let back = parse(&write(&mesh)).unwrap();
+        let triangles = |mesh: &Mesh| -> Vec<[Vec3; 3]> {
+            (0..mesh.triangles.len())
+                .map(|i| mesh.triangle(i))
+                .collect()
+        };
+        assert_eq!(triangles(&back), triangles(&mesh));
+    }
+}
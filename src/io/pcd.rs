@@ -0,0 +1,207 @@
+use anyhow::anyhow;
+use rerun::external::glam;
+
+use crate::cloud::PointCloud;
+
+/// Point Cloud Library files with `DATA ascii` or `DATA binary`; `x y z` plus
+/// optional `normal_x normal_y normal_z` and packed `rgb`/`rgba`
+pub fn parse(bytes: &[u8]) -> anyhow::Result<PointCloud> {
+    let mut fields: Vec<String> = vec![];
+    let mut sizes: Vec<usize> = vec![];
+    let mut types: Vec<u8> = vec![];
+    let mut counts: Vec<usize> = vec![];
+    let mut points = None;
+
+    let mut at = 0;
+    let binary = loop {
+        let end = bytes[at..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(bytes.len(), |i| at + i + 1);
+        if at == end {
+            Err(anyhow!("PCD header has no DATA line"))?
+        }
+        let line = std::str::from_utf8(&bytes[at..end])?;
+        at = end;
+
+        let mut words = line.split_whitespace();
+        let Some(key) = words.next() else { continue };
+        let words: Vec<&str> = words.collect();
+        match key.to_ascii_uppercase().as_str() {
+            "FIELDS" => fields = words.iter().map(|w| w.to_string()).collect(),
+            "SIZE" => sizes = words.iter().map(|w| w.parse()).collect::<Result<_, _>>()?,
+            "TYPE" => types = words.iter().map(|w| w.as_bytes()[0]).collect(),
+            "COUNT" => counts = words.iter().map(|w| w.parse()).collect::<Result<_, _>>()?,
+            "POINTS" => points = Some(words.first().unwrap_or(&"").parse::<usize>()?),
+            "DATA" => match words.first().copied() {
+                Some("ascii") => break false,
+                Some("binary") => break true,
+                data => Err(anyhow!("unsupported PCD data: {}", data.unwrap_or("none")))?,
+            },
+            _ => {}
+        }
+    };
+
+    if counts.is_empty() {
+        counts = vec![1; fields.len()];
+    }
+    if sizes.len() != fields.len() || types.len() != fields.len() || counts.len() != fields.len() {
+        Err(anyhow!("PCD FIELDS, SIZE, TYPE and COUNT disagree"))?
+    }
+    let points = points.ok_or_else(|| anyhow!("PCD header has no POINTS"))?;
+    let stride: usize = sizes.iter().zip(&counts).map(|(s, c)| s * c).sum();
+    if binary && stride == 0 {
+        Err(anyhow!("PCD fields have no size"))?
+    }
+
+    // first value of every field, per point; packed colours keep their bits
+    let mut rows: Vec<Vec<f64>> = Vec::with_capacity(points);
+    if binary {
+        for point in bytes[at..].chunks_exact(stride).take(points) {
+            let mut offset = 0;
+            let mut row = Vec::with_capacity(fields.len());
+            for i in 0..fields.len() {
+                let bytes = &point[offset..offset + sizes[i]];
+                row.push(if fields[i].starts_with("rgb") {
+                    read(bytes, b'U')?
+                } else {
+                    read(bytes, types[i])?
+                });
+                offset += sizes[i] * counts[i];
+            }
+            rows.push(row);
+        }
+    } else {
+        let body = std::str::from_utf8(&bytes[at..])?;
+        for line in body.lines().filter(|l| !l.trim().is_empty()).take(points) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let mut offset = 0;
+            let mut row = Vec::with_capacity(fields.len());
+            for i in 0..fields.len() {
+                let word = words
+                    .get(offset)
+                    .ok_or_else(|| anyhow!("short PCD row: {line}"))?;
+                row.push(if fields[i].starts_with("rgb") && types[i] == b'F' {
+                    word.parse::<f32>()?.to_bits() as f64
+                } else {
+                    word.parse()?
+                });
+                offset += counts[i];
+            }
+            rows.push(row);
+        }
+    }
+    if rows.len() < points {
+        Err(anyhow!("PCD body ends early"))?
+    }
+
+    let field = |name: &str| fields.iter().position(|f| f == name);
+    let (Some(x), Some(y), Some(z)) = (field("x"), field("y"), field("z")) else {
+        Err(anyhow!("PCD has no x, y and z fields"))?
+    };
+    let normals = match (field("normal_x"), field("normal_y"), field("normal_z")) {
+        (Some(nx), Some(ny), Some(nz)) => Some([nx, ny, nz]),
+        _ => None,
+    };
+    let rgb = field("rgb").or_else(|| field("rgba"));
+
+    let mut cloud = PointCloud::default();
+    for row in rows {
+        let p = glam::vec3(row[x] as f32, row[y] as f32, row[z] as f32);
+        // unorganized scans mark missing returns with NaN
+        if !p.is_finite() {
+            continue;
+        }
+        cloud.positions.push(p);
+        if let Some([nx, ny, nz]) = normals {
+            cloud
+                .normals
+                .push(glam::vec3(row[nx] as f32, row[ny] as f32, row[nz] as f32));
+        }
+        if let Some(rgb) = rgb {
+            let bits = row[rgb] as u32;
+            cloud
+                .colors
+                .push([(bits >> 16) as u8, (bits >> 8) as u8, bits as u8]);
+        }
+    }
+    Ok(cloud)
+}
+
+/// One little endian value of PCD type `I`, `U` or `F`
+fn read(bytes: &[u8], ty: u8) -> anyhow::Result<f64> {
+    macro_rules! read {
+        ($t:ty) => {
+            <$t>::from_le_bytes(bytes.try_into()?) as f64
+        };
+    }
+    Ok(match (ty, bytes.len()) {
+        (b'I', 1) => read!(i8),
+        (b'I', 2) => read!(i16),
+        (b'I', 4) => read!(i32),
+        (b'U', 1) => read!(u8),
+        (b'U', 2) => read!(u16),
+        (b'U', 4) => read!(u32),
+        (b'F', 4) => read!(f32),
+        (b'F', 8) => read!(f64),
+        (ty, size) => Err(anyhow!("unsupported PCD field: {} {size}", ty as char))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_with_normals_colours_and_missing_returns() {
+        let rgb = f32::from_bits(0x00ff8000);
+        let text = format!(
+            "# .PCD v0.7\nVERSION 0.7\n\
+            FIELDS x y z normal_x normal_y normal_z rgb\n\
+            SIZE 4 4 4 4 4 4 4\nTYPE F F F F F F F\nCOUNT 1 1 1 1 1 1 1\n\
+            WIDTH 2\nHEIGHT 1\nPOINTS 2\nDATA ascii\n\
+            1 2 3 0 0 1 {rgb:e}\nnan nan nan 0 0 1 {rgb:e}\n"
+        );
+        let cloud = parse(text.as_bytes()).unwrap();
+        assert_eq!(cloud.positions, vec![glam::vec3(1.0, 2.0, 3.0)]);
+        assert_eq!(cloud.normals, vec![glam::Vec3::Z]);
+        assert_eq!(cloud.colors, vec![[255, 128, 0]]);
+    }
+
+    #[test]
+    fn binary_with_mixed_types_and_counts() {
+        let mut bytes = b"FIELDS x y z pad i\nSIZE 4 4 8 1 2\nTYPE F F F U I\n\
+            COUNT 1 1 1 3 1\nPOINTS 2\nDATA binary\n"
+            .to_vec();
+        for (x, y, z) in [(1.0f32, 2.0f32, 3.0f64), (-1.0, -2.0, -3.0)] {
+            bytes.extend(x.to_le_bytes());
+            bytes.extend(y.to_le_bytes());
+            bytes.extend(z.to_le_bytes());
+            bytes.extend([7, 8, 9]);
+            bytes.extend((-5i16).to_le_bytes());
+        }
+        let cloud = parse(&bytes).unwrap();
+        assert_eq!(
+            cloud.positions,
+            vec![glam::vec3(1.0, 2.0, 3.0), glam::vec3(-1.0, -2.0, -3.0)]
+        );
+        assert!(cloud.normals.is_empty() && cloud.colors.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_headers_and_bodies() {
+        for text in [
+            "FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nPOINTS 1\n",
+            "FIELDS x y z\nSIZE 4 4\nTYPE F F F\nPOINTS 1\nDATA ascii\n1 2 3\n",
+            "FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nDATA ascii\n1 2 3\n",
+            "FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nPOINTS 1\nDATA binary_compressed\n",
+            "FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nPOINTS 2\nDATA ascii\n1 2 3\n",
+            "FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nPOINTS 1\nDATA ascii\n1 2\n",
+            "FIELDS a b c\nSIZE 4 4 4\nTYPE F F F\nPOINTS 1\nDATA ascii\n1 2 3\n",
+            "FIELDS x y z\nSIZE 0 0 0\nTYPE F F F\nPOINTS 1\nDATA binary\n",
+            "FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nPOINTS 1\nDATA binary\n\0\0\0\0",
+        ] {
+            assert!(parse(text.as_bytes()).is_err(), "{text:?}");
+        }
+    }
+}
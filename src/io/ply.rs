@@ -0,0 +1,455 @@
+use anyhow::anyhow;
+use rerun::external::glam::{self, Vec3};
+
+use super::xyz;
+use crate::{cloud::PointCloud, mesh::Mesh};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            name => Err(anyhow!("unknown PLY type: {name}"))?,
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    fn read(&self, bytes: &[u8], big_endian: bool) -> f64 {
+        macro_rules! read {
+            ($t:ty) => {{
+                let b = bytes[..size_of::<$t>()].try_into().unwrap();
+                if big_endian {
+                    <$t>::from_be_bytes(b) as f64
+                } else {
+                    <$t>::from_le_bytes(b) as f64
+                }
+            }};
+        }
+        match self {
+            Scalar::I8 => read!(i8),
+            Scalar::U8 => read!(u8),
+            Scalar::I16 => read!(i16),
+            Scalar::U16 => read!(u16),
+            Scalar::I32 => read!(i32),
+            Scalar::U32 => read!(u32),
+            Scalar::F32 => read!(f32),
+            Scalar::F64 => read!(f64),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Property {
+    Scalar(String, Scalar),
+    /// name, type of the count, type of the items
+    List(String, Scalar, Scalar),
+}
+
+impl Property {
+    pub fn name(&self) -> &str {
+        match self {
+            Property::Scalar(name, _) | Property::List(name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Scalar(f64),
+    List(Vec<f64>),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Element {
+    pub name: String,
+    pub count: usize,
+    pub properties: Vec<Property>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl Element {
+    fn position(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|p| p.name() == name)
+    }
+
+    /// Values of a scalar property, if every row has one
+    pub fn scalars(&self, name: &str) -> Option<Vec<f64>> {
+        let i = self.position(name)?;
+        self.rows
+            .iter()
+            .map(|row| match row[i] {
+                Value::Scalar(v) => Some(v),
+                Value::List(_) => None,
+            })
+            .collect()
+    }
+
+    /// Values of a list property, if every row has one
+    pub fn lists(&self, name: &str) -> Option<Vec<&[f64]>> {
+        let i = self.position(name)?;
+        self.rows
+            .iter()
+            .map(|row| match &row[i] {
+                Value::List(v) => Some(v.as_slice()),
+                Value::Scalar(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// Every element of a PLY file, ASCII or binary
+#[derive(Clone, Debug, Default)]
+pub struct Ply {
+    pub elements: Vec<Element>,
+}
+
+impl Ply {
+    pub fn element(&self, name: &str) -> Option<&Element> {
+        self.elements.iter().find(|e| e.name == name)
+    }
+
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        const END: &[u8] = b"end_header";
+        let end = bytes
+            .windows(END.len())
+            .position(|w| w == END)
+            .ok_or_else(|| anyhow!("PLY header has no end_header"))?;
+        let body_start = bytes[end..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(bytes.len(), |i| end + i + 1);
+        let header = std::str::from_utf8(&bytes[..end])?;
+
+        let mut lines = header.lines();
+        if lines.next().map(str::trim) != Some("ply") {
+            Err(anyhow!("not a PLY file"))?
+        }
+
+        let mut format = None;
+        let mut elements: Vec<Element> = vec![];
+        for line in lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["format", "ascii", ..] => format = Some(Format::Ascii),
+                ["format", "binary_little_endian", ..] => format = Some(Format::LittleEndian),
+                ["format", "binary_big_endian", ..] => format = Some(Format::BigEndian),
+                ["element", name, count] => elements.push(Element {
+                    name: name.to_string(),
+                    count: count.parse()?,
+                    ..Default::default()
+                }),
+                ["property", "list", count, item, name] => elements
+                    .last_mut()
+                    .ok_or_else(|| anyhow!("property before element"))?
+                    .properties
+                    .push(Property::List(
+                        name.to_string(),
+                        Scalar::parse(count)?,
+                        Scalar::parse(item)?,
+                    )),
+                ["property", ty, name] => elements
+                    .last_mut()
+                    .ok_or_else(|| anyhow!("property before element"))?
+                    .properties
+                    .push(Property::Scalar(name.to_string(), Scalar::parse(ty)?)),
+                _ => {}
+            }
+        }
+
+        let body = &bytes[body_start..];
+        match format.ok_or_else(|| anyhow!("PLY header has no format"))? {
+            Format::Ascii => read_ascii(std::str::from_utf8(body)?, &mut elements)?,
+            Format::LittleEndian => read_binary(body, false, &mut elements)?,
+            Format::BigEndian => read_binary(body, true, &mut elements)?,
+        }
+        Ok(Self { elements })
+    }
+}
+
+fn read_ascii(body: &str, elements: &mut [Element]) -> anyhow::Result<()> {
+    let mut words = body.split_whitespace();
+    let mut next = || -> anyhow::Result<f64> {
+        Ok(words
+            .next()
+            .ok_or_else(|| anyhow!("PLY body ends early"))?
+            .parse()?)
+    };
+
+    for element in elements {
+        for _ in 0..element.count {
+            let mut row = Vec::with_capacity(element.properties.len());
+            for property in &element.properties {
+                row.push(match property {
+                    Property::Scalar(..) => Value::Scalar(next()?),
+                    Property::List(..) => {
+                        let n = next()? as usize;
+                        Value::List((0..n).map(|_| next()).collect::<anyhow::Result<_>>()?)
+                    }
+                });
+            }
+            element.rows.push(row);
+        }
+    }
+    Ok(())
+}
+
+fn read_binary(body: &[u8], big_endian: bool, elements: &mut [Element]) -> anyhow::Result<()> {
+    let mut at = 0;
+    let mut next = |scalar: Scalar| -> anyhow::Result<f64> {
+        let bytes = body
+            .get(at..at + scalar.size())
+            .ok_or_else(|| anyhow!("PLY body ends early"))?;
+        at += scalar.size();
+        Ok(scalar.read(bytes, big_endian))
+    };
+
+    for element in elements {
+        for _ in 0..element.count {
+            let mut row = Vec::with_capacity(element.properties.len());
+            for property in &element.properties {
+                row.push(match property {
+                    Property::Scalar(_, scalar) => Value::Scalar(next(*scalar)?),
+                    Property::List(_, count, item) => {
+                        let n = next(*count)? as usize;
+                        Value::List((0..n).map(|_| next(*item)).collect::<anyhow::Result<_>>()?)
+                    }
+                });
+            }
+            element.rows.push(row);
+        }
+    }
+    Ok(())
+}
+
+impl Ply {
+    fn vertex(&self) -> anyhow::Result<&Element> {
+        self.element("vertex")
+            .ok_or_else(|| anyhow!("PLY has no vertex element"))
+    }
+
+    /// The first of `names` that is a scalar property of every vertex
+    fn vertex_columns(&self, names: [&[&str]; 3]) -> anyhow::Result<Option<Vec<Vec3>>> {
+        let vertex = self.vertex()?;
+        let column = |names: &[&str]| names.iter().find_map(|name| vertex.scalars(name));
+        let (Some(x), Some(y), Some(z)) = (column(names[0]), column(names[1]), column(names[2]))
+        else {
+            return Ok(None);
+        };
+        Ok(Some(
+            (0..vertex.count)
+                .map(|i| glam::vec3(x[i] as f32, y[i] as f32, z[i] as f32))
+                .collect(),
+        ))
+    }
+
+    fn positions(&self) -> anyhow::Result<Vec<Vec3>> {
+        self.vertex_columns([&["x"], &["y"], &["z"]])?
+            .ok_or_else(|| anyhow!("PLY vertex has no x, y and z"))
+    }
+
+    /// Vertices and faces, faces are fanned into triangles
+    pub fn mesh(&self) -> anyhow::Result<Mesh> {
+        let mut mesh = Mesh {
+            positions: self.positions()?,
+            ..Default::default()
+        };
+
+        if let Some(face) = self.element("face") {
+            let count = mesh.positions.len();
+            let faces = face
+                .lists("vertex_indices")
+                .or_else(|| face.lists("vertex_index"))
+                .ok_or_else(|| anyhow!("PLY face has no vertex_indices"))?;
+            for f in faces {
+                if f.iter().any(|i| *i < 0.0 || *i as usize >= count) {
+                    Err(anyhow!("PLY face index out of range"))?
+                }
+                for i in 1..f.len().saturating_sub(1) {
+                    mesh.triangles
+                        .push([f[0] as u32, f[i] as u32, f[i + 1] as u32]);
+                }
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    /// Vertices with their normals (`nx ny nz`) and colours (`red green blue`)
+    /// when present, faces are ignored
+    pub fn cloud(&self) -> anyhow::Result<PointCloud> {
+        let normals = self.vertex_columns([&["nx"], &["ny"], &["nz"]])?;
+        let colors = self.vertex_columns([
+            &["red", "diffuse_red", "r"],
+            &["green", "diffuse_green", "g"],
+            &["blue", "diffuse_blue", "b"],
+        ])?;
+        let vertex = self.vertex()?;
+        let float = vertex.properties.iter().any(|p| {
+            matches!(p, Property::Scalar(name, Scalar::F32 | Scalar::F64) if name.ends_with("red"))
+        });
+        let colors = colors.map(|colors| match float {
+            true => {
+                let scale = xyz::color_scale(colors.iter().copied());
+                colors.into_iter().map(|c| xyz::color(c, scale)).collect()
+            }
+            false => colors
+                .into_iter()
+                .map(|c| c.to_array().map(|v| v as u8))
+                .collect(),
+        });
+        Ok(PointCloud {
+            positions: self.positions()?,
+            normals: normals.unwrap_or_default(),
+            colors: colors.unwrap_or_default(),
+        })
+    }
+
+    pub fn has_faces(&self) -> bool {
+        self.element("face").is_some_and(|face| face.count > 0)
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> anyhow::Result<Mesh> {
+    Ply::parse(bytes)?.mesh()
+}
+
+pub fn parse_cloud(bytes: &[u8]) -> anyhow::Result<PointCloud> {
+    Ply::parse(bytes)?.cloud()
+}
+
+/// ASCII PLY with `vertex_indices` faces
+pub fn write(mesh: &Mesh) -> String {
+    let mut text = format!(
+        "ply\nformat ascii 1.0\nelement vertex {}\n\
+         property float x\nproperty float y\nproperty float z\n\
+         element face {}\nproperty list uchar uint vertex_indices\nend_header\n",
+        mesh.positions.len(),
+        mesh.triangles.len()
+    );
+    for p in &mesh.positions {
+        text.push_str(&format!("{} {} {}\n", p.x, p.y, p.z));
+    }
+    for [a, b, c] in &mesh.triangles {
+        text.push_str(&format!("3 {a} {b} {c}\n"));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "ply\nformat {format} 1.0\ncomment a square\nelement vertex 4\n\
+        property float x\nproperty float y\nproperty float z\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    const SQUARE: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ];
+
+    fn header(format: &str) -> String {
+        HEADER.replace("{format}", format)
+    }
+
+    fn check(mesh: &Mesh) {
+        assert_eq!(mesh.positions, SQUARE.map(Vec3::from_array).to_vec());
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn ascii() {
+        let text = header("ascii") + "0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n";
+        check(&parse(text.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn binary_in_both_byte_orders() {
+        for (format, big_endian) in [("binary_little_endian", false), ("binary_big_endian", true)] {
+            let mut bytes = header(format).into_bytes();
+            let push = |bytes: &mut Vec<u8>, le: &[u8], be: &[u8]| {
+                bytes.extend(if big_endian { be } else { le });
+            };
+            for x in SQUARE.iter().flatten() {
+                push(&mut bytes, &x.to_le_bytes(), &x.to_be_bytes());
+            }
+            bytes.push(4);
+            for i in 0..4i32 {
+                push(&mut bytes, &i.to_le_bytes(), &i.to_be_bytes());
+            }
+            check(&parse(&bytes).unwrap());
+        }
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        assert!(parse(b"ply\nformat ascii 1.0\nelement vertex 1\n").is_err());
+        let short = header("ascii") + "0 0 0\n1 0 0\n";
+        assert!(parse(short.as_bytes()).is_err());
+        let out_of_range = header("ascii") + "0 0 0\n1 0 0\n1 1 0\n0 1 0\n3 0 1 9\n";
+        assert!(parse(out_of_range.as_bytes()).is_err());
+        let truncated = header("binary_little_endian").into_bytes();
+        assert!(parse(&truncated).is_err());
+    }
+
+    #[test]
+    fn float_colours_share_one_scale() {
+        let text = "ply\nformat ascii 1.0\nelement vertex 2\n\
+            property float x\nproperty float y\nproperty float z\n\
+            property float red\nproperty float green\nproperty float blue\nend_header\n\
+            0 0 0 255 128 0\n1 0 0 1 0 0\n";
+        let cloud = parse_cloud(text.as_bytes()).unwrap();
+        assert_eq!(cloud.colors, vec![[255, 128, 0], [1, 0, 0]]);
+        let unit = text.replace("255 128 0", "1 0.5 0");
+        let cloud = parse_cloud(unit.as_bytes()).unwrap();
+        assert_eq!(cloud.colors, vec![[255, 128, 0], [255, 0, 0]]);
+    }
+
+    #[test]
+    fn written_meshes_read_back() {
+        let mesh =
+            crate::mesh::tests::cuboid(Vec3::new(-1.5, 0.001, 2.0), Vec3::new(3.125, 0.25, 7.0));
+        let back = parse(write(&mesh).as_bytes()).unwrap();
+        let triangles = |mesh: &Mesh| -> Vec<[Vec3; 3]> {
+            (0..mesh.triangles.len())
+                .map(|i| mesh.triangle(i))
+                .collect()
+        };
+        assert_eq!(triangles(&back), triangles(&mesh));
+    }
+}
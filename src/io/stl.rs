@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use rerun::external::glam::{self, Vec3};
+
+use crate::mesh::Mesh;
+
+/// Binary STL is an 80 byte header, a triangle count and 50 bytes per triangle;
+/// anything else starting with `solid` is read as ASCII
+pub fn parse(bytes: &[u8]) -> anyhow::Result<Mesh> {
+    if bytes.len() >= 84 {
+        let count = u32::from_le_bytes(bytes[80..84].try_into()?) as usize;
+        if bytes.len() == 84 + count * 50 {
+            return parse_binary(&bytes[84..], count);
+        }
+    }
+    if bytes.trim_ascii_start().starts_with(b"solid") {
+        return parse_ascii(std::str::from_utf8(bytes)?);
+    }
+    Err(anyhow!("not an STL file"))
+}
+
+fn parse_binary(bytes: &[u8], count: usize) -> anyhow::Result<Mesh> {
+    let mut welder = Welder::default();
+    for facet in bytes.chunks_exact(50).take(count) {
+        // skip the normal, the winding is what we keep
+        let mut tri = [0; 3];
+        for (v, chunk) in tri.iter_mut().zip(facet[12..48].chunks_exact(12)) {
+            let f = |i: usize| f32::from_le_bytes(chunk[i..i + 4].try_into().unwrap());
+            *v = welder.index(glam::vec3(f(0), f(4), f(8)));
+        }
+        welder.mesh.triangles.push(tri);
+    }
+    Ok(welder.mesh)
+}
+
+fn parse_ascii(text: &str) -> anyhow::Result<Mesh> {
+    let mut welder = Welder::default();
+    let mut facet = vec![];
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("vertex") => {
+                let mut coord = || -> anyhow::Result<f32> {
+                    Ok(words
+                        .next()
+                        .ok_or_else(|| anyhow!("short vertex: {line}"))?
+                        .parse()?)
+                };
+                let v = glam::vec3(coord()?, coord()?, coord()?);
+                facet.push(welder.index(v));
+            }
+            Some("endloop") => {
+                // facets are triangles in practice, fan anything larger
+                for i in 1..facet.len().saturating_sub(1) {
+                    welder
+                        .mesh
+                        .triangles
+                        .push([facet[0], facet[i], facet[i + 1]]);
+                }
+                facet.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(welder.mesh)
+}
+
+/// STL repeats every vertex per facet, share identical ones so the mesh is indexed
+#[derive(Default)]
+struct Welder {
+    mesh: Mesh,
+    seen: HashMap<[u32; 3], u32>,
+}
+
+impl Welder {
+    fn index(&mut self, v: Vec3) -> u32 {
+        let key = v.to_array().map(f32::to_bits);
+        *self.seen.entry(key).or_insert_with(|| {
+            self.mesh.positions.push(v);
+            self.mesh.positions.len() as u32 - 1
+        })
+    }
+}
+
+/// Binary STL, facet normals from the winding
+pub fn write(mesh: &Mesh) -> Vec<u8> {
+    let mut bytes = vec![0; 80];
+    bytes.extend((mesh.triangles.len() as u32).to_le_bytes());
+    for i in 0..mesh.triangles.len() {
+        let [a, b, c] = mesh.triangle(i);
+        let normal = (b - a).cross(c - a).normalize_or_zero();
+        for v in [normal, a, b, c] {
+            for x in v.to_array() {
+                bytes.extend(x.to_le_bytes());
+            }
+        }
+        bytes.extend([0, 0]);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII: &str = "solid square
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 0 0
+    vertex 1 1 0
+  endloop
+endfacet
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 1 0
+    vertex 0 1 0
+  endloop
+endfacet
+endsolid square
+";
+
+    #[test]
+    fn ascii_vertices_are_welded() {
+        let mesh = parse(ASCII.as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.positions[3], glam::vec3(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn binary() {
+        let mut bytes = vec![0; 80];
+        bytes.extend(2u32.to_le_bytes());
+        for tri in [
+            [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+        ] {
+            bytes.extend([0u8; 12]);
+            for x in tri {
+                bytes.extend((x as f32).to_le_bytes());
+            }
+            bytes.extend([0, 0]);
+        }
+        let mesh = parse(&bytes).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn binary_starting_with_solid_is_still_binary() {
+        let mut bytes = b"solid but binary".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend(0u32.to_le_bytes());
+        let mesh = parse(&bytes).unwrap();
+        assert!(mesh.is_empty());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(parse(b"hello").is_err());
+        assert!(parse(b"solid x\nvertex 1 2\nendloop\n").is_err());
+    }
+
+    #[test]
+    fn written_meshes_read_back() {
+        let mesh =
+            crate::mesh::tests::cuboid(Vec3::new(-1.5, 0.001, 2.0), Vec3::new(3.125, 0.25, 7.0));
+        let back = parse(&write(&mesh)).unwrap();
+        let triangles = |mesh: &Mesh| -> Vec<[Vec3; 3]> {
+            (0..mesh.triangles.len())
+                .map(|i| mesh.triangle(i))
+                .collect()
+        };
+        assert_eq!(triangles(&back), triangles(&mesh));
+    }
+}
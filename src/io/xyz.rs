@@ -0,0 +1,137 @@
+use anyhow::anyhow;
+use rerun::external::glam::{self, Vec3};
+
+use crate::cloud::PointCloud;
+
+/// Whitespace or comma separated rows of `x y z`, optionally followed by
+/// `nx ny nz`, `r g b` or both; `#` starts a comment
+pub fn parse(text: &str) -> anyhow::Result<PointCloud> {
+    let mut rows = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let row = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|w| !w.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|e| anyhow!("line {}: {e}", n + 1))?;
+        match row.len() {
+            0 => {}
+            3 | 6 | 9 => rows.push(row),
+            len => Err(anyhow!(
+                "line {}: expected 3, 6 or 9 columns, got {len}",
+                n + 1
+            ))?,
+        }
+    }
+
+    let columns = rows.first().map_or(3, Vec::len);
+    if rows.iter().any(|row| row.len() != columns) {
+        Err(anyhow!("rows have differing column counts"))?
+    }
+    let at = |row: &[f32], i: usize| glam::vec3(row[i], row[i + 1], row[i + 2]);
+    // six columns are normals when they are unit length, colours otherwise
+    let normals_first = columns == 9
+        || (columns == 6
+            && rows
+                .iter()
+                .all(|row| (at(row, 3).length() - 1.0).abs() < 1e-2));
+
+    let rest = match normals_first {
+        true => 6,
+        false => 3,
+    };
+    let scale = color_scale(
+        rows.iter()
+            .filter(|row| row.len() > rest)
+            .map(|row| at(row, rest)),
+    );
+
+    let mut cloud = PointCloud::default();
+    for row in &rows {
+        cloud.positions.push(at(row, 0));
+        if normals_first {
+            cloud.normals.push(at(row, 3));
+        }
+        if columns > rest {
+            cloud.colors.push(color(at(row, rest), scale));
+        }
+    }
+    Ok(cloud)
+}
+
+/// What a file's colours are multiplied by: they are either 0..=255 or, if
+/// no channel of any of them exceeds one, 0..=1
+pub(super) fn color_scale(colors: impl IntoIterator<Item = Vec3>) -> f32 {
+    let max = colors
+        .into_iter()
+        .map(Vec3::max_element)
+        .fold(0.0, f32::max);
+    match max <= 1.0 {
+        true => 255.0,
+        false => 1.0,
+    }
+}
+
+/// A colour of a file with [`color_scale`] `scale` as bytes
+pub(super) fn color(c: Vec3, scale: f32) -> [u8; 3] {
+    (c * scale)
+        .clamp(Vec3::ZERO, Vec3::splat(255.0))
+        .round()
+        .to_array()
+        .map(|v| v as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_with_comments_and_commas() {
+        let cloud = parse("# scan\n1 2 3\n\n4,5,6 # last\n").unwrap();
+        assert_eq!(
+            cloud.positions,
+            vec![glam::vec3(1.0, 2.0, 3.0), glam::vec3(4.0, 5.0, 6.0)]
+        );
+        assert!(cloud.normals.is_empty() && cloud.colors.is_empty());
+    }
+
+    #[test]
+    fn six_columns_are_normals_or_colours() {
+        let normals = parse("0 0 0 0 0 1\n1 1 1 1 0 0\n").unwrap();
+        assert_eq!(normals.normals, vec![Vec3::Z, Vec3::X]);
+        assert!(normals.colors.is_empty());
+
+        let colors = parse("0 0 0 255 128 0\n1 1 1 0 0 10\n").unwrap();
+        assert!(colors.normals.is_empty());
+        assert_eq!(colors.colors, vec![[255, 128, 0], [0, 0, 10]]);
+        // dark points of a 0..=255 file stay dark
+        let dark = parse("0 0 0 255 128 0\n1 1 1 1 0 0\n").unwrap();
+        assert_eq!(dark.colors, vec![[255, 128, 0], [1, 0, 0]]);
+    }
+
+    #[test]
+    fn nine_columns_are_normals_then_colours() {
+        let cloud = parse("0 0 0 0 1 0 1 0.5 0\n").unwrap();
+        assert_eq!(cloud.normals, vec![Vec3::Y]);
+        assert_eq!(cloud.colors, vec![[255, 128, 0]]);
+    }
+
+    #[test]
+    fn rejects_bad_rows() {
+        for text in ["1 2\n", "1 2 3\n1 2 3 4 5 6\n", "1 2 x\n"] {
+            assert!(parse(text).is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn colours_scale_to_bytes() {
+        let unit = [glam::vec3(1.0, 0.5, 0.0), glam::vec3(0.2, 0.0, 0.0)];
+        assert_eq!(color_scale(unit), 255.0);
+        assert_eq!(color(unit[0], 255.0), [255, 128, 0]);
+        let bytes = [glam::vec3(300.0, 2.0, -1.0), glam::vec3(1.0, 0.0, 0.0)];
+        assert_eq!(color_scale(bytes), 1.0);
+        assert_eq!(color(bytes[0], 1.0), [255, 2, 0]);
+        assert_eq!(color(bytes[1], 1.0), [1, 0, 0]);
+    }
+}
@@ -1,15 +1,47 @@
 #![feature(slice_split_once)]
 
-use pyo3::prelude::*;
+use std::path::PathBuf;
+
+use pyo3::{exceptions::PyValueError, prelude::*};
 use rand::prelude::*;
 use rerun::{
     external::glam::{self, Vec3},
     RecordingStream,
 };
 
-use crate::microcad::{generate, Microcad};
+use crate::{
+    cloud::CloudFilter,
+    generator::GeneratorConfig,
+    mesh::params_to_mesh,
+    microcad::{generate, Microcad},
+    scan::Scanner,
+    score::{Candidate, ScoreSpec},
+    sdf::ProgramSdf,
+};
 
+pub mod align;
+pub mod analysis;
+pub mod bvh;
+pub mod cloud;
+pub mod generator;
+pub mod grid;
+pub mod io;
+pub mod mesh;
+pub mod mesher;
+pub mod metric;
 pub mod microcad;
+pub mod optimize;
+pub mod repl;
+pub mod sample;
+pub mod scan;
+pub mod score;
+pub mod sdf;
+pub mod search;
+pub mod transform;
+pub mod volume;
+
+pub use metric::chamfer_distance;
+pub use volume::{sampled_iou, voxel_iou};
 
 #[pyfunction]
 fn pyvisualize(kinds: Vec<u8>, params: Vec<f32>) -> PyResult<()> {
@@ -32,57 +64,147 @@ fn pyvisualize(kinds: Vec<u8>, params: Vec<f32>) -> PyResult<()> {
     Ok(())
 }
 
-#[pymodule]
-fn paramesh(m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(pyvisualize, m)?)?;
-    Ok(())
+fn to_pyerr(e: anyhow::Error) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// Score a program against a target program with a scorer spec like `surface+0.1*complexity`
+#[pyfunction]
+#[pyo3(signature = (target_kinds, target_params, kinds, params, score = "chamfer"))]
+fn pyscore(
+    target_kinds: Vec<u8>,
+    target_params: Vec<f32>,
+    kinds: Vec<u8>,
+    params: Vec<f32>,
+    score: &str,
+) -> PyResult<f32> {
+    let target = params_to_mesh(&target_kinds, &target_params).map_err(to_pyerr)?;
+    let mut scorer = score.parse::<ScoreSpec>().map_err(to_pyerr)?.build();
+    scorer.prepare(&target);
+    scorer
+        .score(&Candidate::new(&kinds, &params))
+        .map_err(to_pyerr)
+}
+
+/// Positions and triangles, as handed to Python
+type PyMesh = (Vec<[f32; 3]>, Vec<[u32; 3]>);
+
+/// Positions and triangles of an STL, OBJ or PLY file
+#[pyfunction]
+fn pyload_mesh(path: PathBuf) -> PyResult<PyMesh> {
+    let mesh = io::load_mesh(path).map_err(to_pyerr)?;
+    let positions = mesh.positions.iter().map(|p| p.to_array()).collect();
+    Ok((positions, mesh.triangles))
+}
+
+/// Points of an XYZ, PCD or PLY file, thinned like the binaries' flags do
+#[pyfunction]
+#[pyo3(signature = (path, outliers = None, voxel = None, max_points = None))]
+fn pyload_cloud(
+    path: PathBuf,
+    outliers: Option<f32>,
+    voxel: Option<f32>,
+    max_points: Option<usize>,
+) -> PyResult<Vec<[f32; 3]>> {
+    let filter = CloudFilter {
+        outliers,
+        voxel,
+        max_points,
+        ..Default::default()
+    };
+    let cloud = filter.apply(&io::load_cloud(path).map_err(to_pyerr)?);
+    Ok(cloud.positions.iter().map(|p| p.to_array()).collect())
+}
+
+/// Simulated scan of a program, for building noisy benchmark targets
+#[pyfunction]
+#[pyo3(signature = (
+    kinds, params, cameras = 3, resolution = 64,
+    noise = 0.0, dropout = 0.0, outliers = 0.0, seed = 0
+))]
+#[allow(clippy::too_many_arguments)]
+fn pyscan(
+    kinds: Vec<u8>,
+    params: Vec<f32>,
+    cameras: usize,
+    resolution: usize,
+    noise: f32,
+    dropout: f32,
+    outliers: f32,
+    seed: u64,
+) -> PyResult<Vec<[f32; 3]>> {
+    let mesh = params_to_mesh(&kinds, &params).map_err(to_pyerr)?;
+    let scanner = Scanner {
+        cameras,
+        resolution,
+        noise,
+        dropout,
+        outliers,
+        seed,
+        ..Default::default()
+    };
+    let cloud = scanner.scan(&mesh);
+    Ok(cloud.positions.iter().map(|p| p.to_array()).collect())
+}
+
+/// Signed distance of each point to a program's solid, negative inside
+#[pyfunction]
+fn pysdf(kinds: Vec<u8>, params: Vec<f32>, points: Vec<[f32; 3]>) -> PyResult<Vec<f32>> {
+    let sdf = ProgramSdf::new(&kinds, &params).map_err(to_pyerr)?;
+    Ok(points
+        .into_iter()
+        .map(|p| sdf.distance(Vec3::from_array(p)))
+        .collect())
 }
 
-enum Primitive {
-    Cube(u8, u8, u8),
-    Sphere(u8),
-    Cylinder(u8, u8),
+/// Occupancy labels of points for a program
+#[pyfunction]
+fn pycontains(kinds: Vec<u8>, params: Vec<f32>, points: Vec<[f32; 3]>) -> PyResult<Vec<bool>> {
+    let sdf = ProgramSdf::new(&kinds, &params).map_err(to_pyerr)?;
+    Ok(points
+        .into_iter()
+        .map(|p| sdf.contains(Vec3::from_array(p)))
+        .collect())
+}
+
+/// Mesh of a program's analytic field, `2^depth` cells along its longest side
+#[pyfunction]
+#[pyo3(signature = (kinds, params, depth = mesher::SDF_DEPTH))]
+fn pymesh_sdf(kinds: Vec<u8>, params: Vec<f32>, depth: u32) -> PyResult<PyMesh> {
+    let sdf = ProgramSdf::new(&kinds, &params).map_err(to_pyerr)?;
+    let mesh = mesher::mesh_sdf(&sdf, depth);
+    let positions = mesh.positions.iter().map(|p| p.to_array()).collect();
+    Ok((positions, mesh.triangles))
 }
 
-enum Operation {
-    Translate(u8, u8, u8),
-    Rotate(u8, u8, u8),
+/// Like `pyscore`, against a mesh, point cloud or µcad file instead of a target program
+#[pyfunction]
+#[pyo3(signature = (path, kinds, params, score = "chamfer"))]
+fn pyscore_mesh(path: PathBuf, kinds: Vec<u8>, params: Vec<f32>, score: &str) -> PyResult<f32> {
+    let target = io::load_target(path, &CloudFilter::default()).map_err(to_pyerr)?;
+    let mut scorer = score.parse::<ScoreSpec>().map_err(to_pyerr)?.build();
+    scorer.prepare(&target);
+    scorer
+        .score(&Candidate::new(&kinds, &params))
+        .map_err(to_pyerr)
 }
 
-pub fn chamfer_distance(a: &[Vec3], b: &[Vec3]) -> f32 {
-    fn nearest_sum(from: &[Vec3], to: &[Vec3]) -> f32 {
-        let mut accum = 0.0;
-        for p in from {
-            let mut best = f32::INFINITY;
-            for q in to {
-                let d = (*p - *q).length_squared();
-                if d < best {
-                    best = d;
-                }
-            }
-            accum += best;
-        }
-        accum / from.len() as f32
-    }
-
-    nearest_sum(a, b) + nearest_sum(b, a)
+#[pymodule]
+fn paramesh(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(pyvisualize, m)?)?;
+    m.add_function(wrap_pyfunction!(pyscore, m)?)?;
+    m.add_function(wrap_pyfunction!(pyload_mesh, m)?)?;
+    m.add_function(wrap_pyfunction!(pyload_cloud, m)?)?;
+    m.add_function(wrap_pyfunction!(pyscore_mesh, m)?)?;
+    m.add_function(wrap_pyfunction!(pyscan, m)?)?;
+    m.add_function(wrap_pyfunction!(pysdf, m)?)?;
+    m.add_function(wrap_pyfunction!(pycontains, m)?)?;
+    m.add_function(wrap_pyfunction!(pymesh_sdf, m)?)?;
+    Ok(())
 }
 
 pub fn generate_random(rng: &mut ThreadRng) -> (u8, [f32; 10]) {
-    let kind = rng.random_range(0..=2);
-    let mut params = [0f32; 10];
-    for p in params.iter_mut().take(3) {
-        *p = rng.random_range(1f32..=20f32)
-    }
-    for p in params.iter_mut().skip(3).take(3) {
-        *p = rng.random_range(0f32..=5f32)
-    }
-    for p in params.iter_mut().skip(6).take(3) {
-        *p = rng.random_range(0f32..=360f32)
-    }
-    params[9] = 0f32;
-
-    (kind, params)
+    GeneratorConfig::default().primitive(rng)
 }
 
 pub fn visualize(target: Vec<Vec3>, rec: &RecordingStream) {
@@ -91,14 +213,5 @@ pub fn visualize(target: Vec<Vec3>, rec: &RecordingStream) {
 }
 
 pub fn params_to_glam(kinds: &[u8], params: &[f32]) -> Vec<Vec3> {
-    let ucad = generate::ucad(kinds, params).unwrap();
-    let mut target = Microcad::new();
-    target.set_root(&ucad);
-    let triags = target.render_mesh().unwrap();
-    let pos = triags
-        .positions
-        .iter()
-        .map(|v| glam::vec3(v.x, v.y, v.z))
-        .collect();
-    pos
+    mesh::params_to_mesh(kinds, params).unwrap().positions
 }
@@ -1,152 +1,782 @@
-use std::{collections::BTreeMap, path::PathBuf, process::exit, thread::sleep, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
+use anyhow::anyhow;
 use clap::Parser;
-use glam::Vec3;
-use itertools::iproduct;
 use paramesh::{
-    chamfer_distance, generate_random,
+    align::{Aligned, Aligner},
+    analysis::Prefilter,
+    bvh::Aabb,
+    cloud::CloudFilter,
+    generator::GeneratorConfig,
+    grid::{Grid, GridConfig, Refine},
+    io,
+    mesh::{Mesh, Renderer},
     microcad::{generate, Microcad},
-    params_to_glam, visualize,
-};
-use rand::{
-    distr::{weighted::WeightedIndex, Uniform},
-    prelude::*,
+    optimize::{polish, OptimizeConfig, Optimizer, ParamBounds},
+    repl::{self, Command},
+    sample::SampleConfig,
+    scan::Scanner,
+    score::{Candidate, ScoreSpec, Scorer},
+    search::{self, Evaluator},
+    transform::{Normalize, Rigid, Scaling},
+    visualize,
 };
-use reedline::{DefaultPrompt, Reedline, Signal};
-use rerun::{external::glam, RecordingStream};
+use rand::prelude::*;
+use reedline::{DefaultPrompt, Signal};
+use rerun::{external::glam::Vec3, RecordingStream};
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::SIGINT;
 
-enum E {
-    Filled(u8, [i8; 10]),
-    Hole,
+#[derive(Parser)]
+struct Args {
+    /// mesh (STL, OBJ, PLY), point cloud (XYZ, PCD, PLY) or µcad program to
+    /// reverse engineer, a random program otherwise
+    #[arg(long)]
+    target: Option<PathBuf>,
+    /// scorer used to rank candidates, e.g. `surface+0.1*complexity`
+    #[arg(long, default_value = "chamfer")]
+    score: ScoreSpec,
+    /// register candidates onto the target before scoring, for targets in
+    /// arbitrary poses, and print committed programs in the target's frame
+    #[arg(long)]
+    align: bool,
+    /// refine the whole program with a continuous optimizer on every commit
+    #[arg(long, value_enum)]
+    polish: Option<Optimizer>,
+    /// move and scale the target before searching, programs are printed back
+    /// in its original units
+    #[arg(long, value_enum, default_value_t)]
+    normalize: Normalize,
+    #[command(flatten)]
+    cloud: CloudFilter,
+    /// replace the target by a simulated scan of it
+    #[arg(long)]
+    scan: bool,
+    #[command(flatten)]
+    scanner: Scanner,
+    #[command(flatten)]
+    generator: GeneratorConfig,
+    /// skip candidates whose bounding sphere, volume or moments exceed the
+    /// target's by this factor before scoring them, 0 scores everything; wrong
+    /// for targets with intersections and for partial scans
+    #[arg(long, default_value_t = 0.0)]
+    prefilter: f32,
+    /// how candidates are turned into meshes
+    #[arg(long, value_enum, default_value_t)]
+    renderer: Renderer,
+    /// write the program's mesh, in the target's units, to this STL, OBJ or PLY
+    /// file on every commit, or once at the end of a batch
+    #[arg(long)]
+    export: Option<PathBuf>,
+    /// seed of the random target, drawn at random otherwise
+    #[arg(long)]
+    seed: Option<u64>,
+    /// continue a saved session, the one saved on the last exit without a file
+    #[arg(long)]
+    resume: Option<Option<PathBuf>>,
+    #[command(flatten)]
+    grid: GridConfig,
+    #[command(flatten)]
+    sampling: SampleConfig,
+    #[command(flatten)]
+    refine: Refine,
+    /// search and commit primitives without the REPL until the program has
+    /// this many, then exit
+    #[arg(long, value_name = "PRIMITIVES")]
+    batch: Option<usize>,
+    /// write the program as µcad to this file on every commit
+    #[arg(long)]
+    ucad: Option<PathBuf>,
+    /// write the session here on exit instead of the autosave file
+    #[arg(long)]
+    save: Option<PathBuf>,
+    /// distinct candidates kept after each search, to commit by rank
+    #[arg(long, default_value_t = 5)]
+    top: usize,
+    /// workers scoring search candidates, 0 for one per core
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
 }
 
 fn main() -> anyhow::Result<()> {
-    let rec = rerun::RecordingStreamBuilder::new("microcad synthesizer").spawn()?;
-
-    let mut rng = rand::rng();
-
-    let count = 5;
-    let mut target = Microcad::new();
-    let (kinds, params): (Vec<u8>, Vec<[f32; 10]>) =
-        (0..2).map(|_| generate_random(&mut rng)).collect();
-    println!("target: {kinds:?}, {params:?}");
-    let params = params.into_iter().flatten().collect::<Vec<_>>();
-    let tgt_ucad = generate::ucad(&kinds, &params)?;
-    println!("{tgt_ucad}");
-    target.set_root(&tgt_ucad);
-    let triags = target.render_mesh()?;
-    let target_positions = triags.positions.iter().map(|v| glam::vec3(v.x, v.y, v.z));
-    let target_mesh: Vec<Vec3> = target_positions.collect();
-    let points = rerun::Points3D::new(target_mesh.clone());
-    rec.log("mesh", &points.with_radii([0.1]))?;
-
-    println!("initial");
-    // sleep(Duration::from_secs(10));
-
-    let mut line_editor = Reedline::create();
+    let args = Args::parse();
+
+    // scripted runs have no one to look at the viewer
+    let rec = match args.batch {
+        Some(_) => RecordingStream::disabled(),
+        None => rerun::RecordingStreamBuilder::new("microcad synthesizer").spawn()?,
+    };
+
+    let resumed = match &args.resume {
+        Some(path) => {
+            let path = path
+                .clone()
+                .or_else(repl::autosave_path)
+                .ok_or_else(|| anyhow!("no session to resume"))?;
+            let session = Session::load(&path)?;
+            println!("resumed {}", path.display());
+            Some(session)
+        }
+        None => None,
+    };
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let target = match (&resumed, &args.target) {
+        (Some(session), _) => session.target.clone(),
+        (None, Some(path)) => Target::File(std::path::absolute(path)?),
+        (None, None) => {
+            let mut rng = rand::rngs::SmallRng::seed_from_u64(seed);
+            let (kinds, params) = args.generator.valid_program(&mut rng)?;
+            Target::Program { kinds, params }
+        }
+    };
+    let mut context = Context::new(args, rec, &target)?;
+    let mut session = resumed.unwrap_or_else(|| Session::new(target, seed, &context));
+
+    if let Some(primitives) = context.args.batch {
+        while session.kinds.len() < primitives {
+            println!("searching primitive {}", session.kinds.len());
+            let cancelled = session.search(&context);
+            match (session.ranked.is_empty(), cancelled) {
+                (false, _) => session.place(None, 0, &context)?,
+                // stopped before anything was scored, e.g. by Ctrl-C while loading
+                (true, true) => {}
+                (true, false) => Err(anyhow!(
+                    "no candidate for primitive {}",
+                    session.kinds.len()
+                ))?,
+            }
+            if cancelled || context.interrupted() {
+                println!("batch cancelled");
+                break;
+            }
+        }
+        session.list(&context);
+        if context.args.export.is_some() && !session.kinds.is_empty() {
+            let (mesh, transform) = placed(&session.kinds, &session.flat_params(), &context)?;
+            export(&mesh, &transform, &context)?;
+        }
+        if let Some(path) = &context.args.save {
+            session.save(path)?;
+            println!("saved {}", path.display());
+        }
+        return Ok(());
+    }
+
+    println!("initial, `help` lists the commands");
+
+    let mut line_editor = repl::line_editor()?;
     let prompt = DefaultPrompt {
         left_prompt: reedline::DefaultPromptSegment::Basic("paramesh".into()),
         right_prompt: reedline::DefaultPromptSegment::Empty,
     };
 
-    let mut built_kinds = vec![];
-    let mut built_params = vec![];
-    let mut best = (0u8, [0.0; 10], 0.0);
-    let mut next = None;
-    let mut size_range = 1u16..=20u16;
-    let mut tran_range = 1u16..=5u16;
-    let mut rota_range = 0u16..=360u16;
-    loop {
-        let input = line_editor.read_line(&prompt)?;
-        match input {
-            Signal::Success(input) => {
-                let mut it = input.chars();
-                match (it.next(), it.next()) {
-                    (Some('k'), Some(k)) => match k {
-                        '0' => next = Some(0),
-                        '1' => next = Some(1),
-                        '2' => next = Some(2),
-                        _ => {}
-                    },
-                    (Some('s'), _) => {
-                        let collect = it.collect::<String>();
-                        let (low, high) = collect.split_once('-').unwrap();
-                        let low = low.parse().unwrap();
-                        let high = high.parse().unwrap();
-                        size_range = low..=high;
-                    }
-                    (Some('t'), _) => {
-                        let collect = it.collect::<String>();
-                        let (low, high) = collect.split_once('-').unwrap();
-                        let low = low.parse().unwrap();
-                        let high = high.parse().unwrap();
-                        tran_range = low..=high;
-                    }
-                    (Some('r'), _) => {
-                        let collect = it.collect::<String>();
-                        let (low, high) = collect.split_once('-').unwrap();
-                        let low = low.parse().unwrap();
-                        let high = high.parse().unwrap();
-                        rota_range = low..=high;
-                    }
-                    (Some('c'), _) => {
-                        built_kinds.push(best.0);
-                        built_params.push(best.1);
+    // Ctrl-C and Ctrl-D end the session like `quit`
+    while let Signal::Success(input) = line_editor.read_line(&prompt)? {
+        // a Ctrl-C during the last line's work is spent, don't stop this one's search
+        context.interrupted();
+        let command = match repl::parse(&input) {
+            Ok(command) => command,
+            Err(e) => {
+                println!("{e}");
+                continue;
+            }
+        };
+        match command {
+            Command::Help(topic) => match repl::help(topic.as_deref()) {
+                Ok(text) => println!("{text}"),
+                Err(e) => println!("{e}"),
+            },
+            Command::Quit => break,
+            Command::Save(path) => match session.save(&path) {
+                Ok(()) => println!("saved {}", path.display()),
+                Err(e) => println!("{e}"),
+            },
+            Command::Load(path) => {
+                let loaded = Session::load(&path).and_then(|loaded| {
+                    if loaded.target != session.target {
+                        context.retarget(&loaded.target)?;
                     }
-                    (Some('a'), _) => {
-                        next = None;
-                        size_range = 1u16..=20u16;
-                        tran_range = 1u16..=5u16;
-                        rota_range = 0u16..=360u16;
+                    Ok(loaded)
+                });
+                match loaded {
+                    Ok(loaded) => {
+                        session = loaded;
+                        println!("loaded {}", path.display());
+                        session.list(&context);
                     }
-                    _ => {}
+                    Err(e) => println!("{e}"),
+                }
+            }
+            command => match session.execute(command, &context) {
+                Ok(true) => {
+                    session.search(&context);
+                }
+                Ok(false) => {}
+                Err(e) => println!("{e}"),
+            },
+        }
+    }
+
+    if let Some(path) = session.autosave(&context)? {
+        println!(
+            "session saved to {}, continue with --resume",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Where a session's target comes from, enough to rebuild it on resume
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum Target {
+    File(PathBuf),
+    /// a random program, kept whole since the generator may change
+    Program {
+        kinds: Vec<u8>,
+        params: Vec<f32>,
+    },
+}
+
+impl Target {
+    fn mesh(&self, args: &Args) -> anyhow::Result<Mesh> {
+        match self {
+            Target::File(path) => {
+                let mesh = io::load_target(path, &args.cloud)?;
+                println!(
+                    "target: {} ({} vertices, {} triangles)",
+                    path.display(),
+                    mesh.positions.len(),
+                    mesh.triangles.len()
+                );
+                Ok(mesh)
+            }
+            Target::Program { kinds, params } => {
+                let mut target = Microcad::new();
+                println!("target: {kinds:?}, {params:?}");
+                let tgt_ucad = generate::ucad(kinds, params)?;
+                println!("{tgt_ucad}");
+                target.set_root(&tgt_ucad);
+                Ok(target.render_mesh()?.into())
+            }
+        }
+    }
+}
+
+/// Everything that stays fixed while the target does
+struct Context {
+    args: Args,
+    scorer: Box<dyn Scorer>,
+    prefilter: Option<Prefilter>,
+    aligner: Option<Aligner>,
+    scaling: Scaling,
+    /// the flags' ranges, filled in from the target
+    grid: Grid,
+    /// width of the target, for laying out ranked candidates beside it
+    extent: f32,
+    rec: RecordingStream,
+    /// set by Ctrl-C, stops a running search or the next one; only
+    /// [`Context::interrupted`] clears it
+    cancel: Arc<AtomicBool>,
+    /// whether the session file this run started from has been backed up
+    backed_up: AtomicBool,
+}
+
+impl Context {
+    fn new(args: Args, rec: RecordingStream, target: &Target) -> anyhow::Result<Self> {
+        // the first Ctrl-C stops a search, a second one before it has wound
+        // down ends the program as usual
+        let cancel = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register_conditional_shutdown(SIGINT, 1, cancel.clone())?;
+        signal_hook::flag::register(SIGINT, cancel.clone())?;
+        let mut context = Self {
+            scorer: args.score.build(),
+            prefilter: None,
+            aligner: None,
+            scaling: Scaling::IDENTITY,
+            grid: Grid::fit(&Mesh::default()),
+            extent: 0.0,
+            rec,
+            cancel,
+            backed_up: AtomicBool::new(false),
+            args,
+        };
+        context.retarget(target)?;
+        Ok(context)
+    }
+
+    /// Whether Ctrl-C was pressed since the last call; a press while
+    /// committing or loading carries over to the search after it
+    fn interrupted(&self) -> bool {
+        self.cancel.swap(false, Ordering::Relaxed)
+    }
+
+    fn evaluator(&self) -> Evaluator<'_> {
+        Evaluator {
+            scorer: self.scorer.as_ref(),
+            prefilter: self.prefilter.as_ref(),
+            renderer: self.args.renderer,
+            threads: self.args.threads,
+        }
+    }
+
+    /// Loads, scans and normalizes `target` and prepares the scorer for it
+    fn retarget(&mut self, target: &Target) -> anyhow::Result<()> {
+        let args = &self.args;
+        let target_mesh = target.mesh(args)?;
+        let target_mesh: Mesh = if args.scan {
+            let cloud = args.cloud.apply(&args.scanner.scan(&target_mesh));
+            println!("scanned: {} points", cloud.len());
+            cloud.into()
+        } else {
+            target_mesh
+        };
+        let scaling = args.normalize.fit(&target_mesh, &ParamBounds::default());
+        if !scaling.is_identity() {
+            println!("normalized: {scaling}");
+        }
+        let target_mesh = scaling.mesh(&target_mesh);
+        self.grid = args.grid.grid(&target_mesh);
+        self.extent = Aabb::from_points(&target_mesh.positions).size().x.max(1.0);
+        println!("search grid: {}", self.grid);
+        let points = rerun::Points3D::new(target_mesh.positions.clone());
+        self.rec.log("mesh", &points.with_radii([0.1]))?;
+
+        let mut scorer = args.score.build();
+        if args.align {
+            scorer = Box::new(Aligned::new(scorer));
+        }
+        scorer.prepare(&target_mesh);
+        // a partial scan or an intersection can be smaller than its parts
+        let prefilter = match args.scan || args.generator.intersections > 0.0 {
+            true => 0.0,
+            false => args.prefilter,
+        };
+        self.prefilter = (prefilter > 0.0).then(|| Prefilter::new(&target_mesh, prefilter));
+        self.aligner = args.align.then(|| Aligner::new(&target_mesh));
+        self.scorer = scorer;
+        self.scaling = scaling;
+        Ok(())
+    }
+}
+
+/// What the REPL has built so far and where it searches next, saved as JSON
+#[derive(Serialize, Deserialize)]
+struct Session {
+    target: Target,
+    seed: u64,
+    kinds: Vec<u8>,
+    params: Vec<[f32; 10]>,
+    /// earlier programs, for `undo`
+    #[serde(skip)]
+    history: Vec<(Vec<u8>, Vec<[f32; 10]>)>,
+    /// primitive searched for with the others fixed, a new one for `None`
+    #[serde(skip)]
+    slot: Option<usize>,
+    /// kind, parameters and score of the last search's best distinct
+    /// candidates, best first
+    #[serde(skip)]
+    ranked: Vec<(u8, [f32; 10], f32)>,
+    next: Option<u8>,
+    grid: Grid,
+    #[serde(default)]
+    sampling: SampleConfig,
+    #[serde(default)]
+    refine: Refine,
+    /// searches run so far, which with the seed seeds the random samplers
+    #[serde(default)]
+    searches: u64,
+    /// whether any command changed the session since it was started or loaded
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl Session {
+    fn new(target: Target, seed: u64, context: &Context) -> Self {
+        Self {
+            target,
+            seed,
+            kinds: vec![],
+            params: vec![],
+            history: vec![],
+            slot: None,
+            ranked: vec![],
+            next: None,
+            grid: context.grid,
+            sampling: context.args.sampling,
+            refine: context.args.refine,
+            searches: 0,
+            changed: false,
+        }
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("cannot read {}: {e}", path.display()))?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Saves to `--save` or, outside batch runs, the autosave file, returning
+    /// where; sessions without a program or changes are not worth a file. The
+    /// file as this run found it is kept next to it with a `.bak` suffix
+    fn autosave(&self, context: &Context) -> anyhow::Result<Option<PathBuf>> {
+        let path = match context.args.batch {
+            Some(_) => context.args.save.clone(),
+            None => context.args.save.clone().or_else(repl::autosave_path),
+        };
+        let Some(path) = path.filter(|_| !self.kinds.is_empty() || self.changed) else {
+            return Ok(None);
+        };
+        if !context.backed_up.swap(true, Ordering::Relaxed) && path.exists() {
+            let mut backup = path.clone().into_os_string();
+            backup.push(".bak");
+            std::fs::copy(&path, backup)?;
+        }
+        self.save(&path)?;
+        Ok(Some(path))
+    }
+
+    /// Runs one command, true when a search should follow it
+    fn execute(&mut self, command: Command, context: &Context) -> anyhow::Result<bool> {
+        let read_only = matches!(
+            command,
+            Command::Top | Command::List | Command::Ucad | Command::Help(_) | Command::Quit
+        );
+        let search = self.apply(command, context)?;
+        self.changed |= !read_only;
+        Ok(search)
+    }
+
+    fn apply(&mut self, command: Command, context: &Context) -> anyhow::Result<bool> {
+        match command {
+            Command::Kind(kind) => self.next = kind,
+            Command::Size(range) => self.grid.size = range,
+            Command::Translation(range) => self.grid.translation = range,
+            Command::Rotation(range) => self.grid.rotation = range,
+            Command::Sample(sampling) => {
+                self.sampling = sampling;
+                println!("sampling {}", self.sampling);
+            }
+            Command::Refine(refine) => {
+                self.refine = refine;
+                println!("refinement {}", self.refine);
+            }
+            Command::Commit(rank) => self.place(self.slot, rank, context)?,
+            Command::Top => {
+                self.top(context);
+                return Ok(false);
+            }
+            Command::Reset => {
+                self.next = None;
+                self.grid = context.grid;
+                self.sampling = context.args.sampling;
+                self.refine = context.args.refine;
+            }
+            Command::Search(slot) => {
+                if let Some(slot) = slot {
+                    self.check_index(slot)?;
                 }
+                self.slot = slot;
             }
-            Signal::CtrlC | Signal::CtrlD => exit(1),
-        }
-
-        let mut best_candi = (f32::MAX, (Vec::new(), (0, [0f32; 10])));
-
-        for (kind, sx, sy, sz, tx, ty, tz, rx, ry, rz) in iproduct!(
-            0..=2,
-            size_range.clone().step_by(10),
-            size_range.clone().step_by(10),
-            size_range.clone().step_by(10),
-            tran_range.clone().step_by(2),
-            tran_range.clone().step_by(2),
-            tran_range.clone().step_by(2),
-            rota_range.clone().step_by(90),
-            rota_range.clone().step_by(90),
-            rota_range.clone().step_by(90),
-        ) {
-            let kind = {
-                if let Some(n) = next {
-                    n
-                } else {
-                    kind
+            Command::List => {
+                self.list(context);
+                return Ok(false);
+            }
+            Command::Ucad => {
+                if self.kinds.is_empty() {
+                    Err(anyhow!("the program is empty"))?
                 }
+                emit(&self.kinds, &self.flat_params(), context)?;
+                return Ok(false);
+            }
+            Command::Undo => {
+                let (kinds, params) = self
+                    .history
+                    .pop()
+                    .ok_or_else(|| anyhow!("nothing to undo"))?;
+                self.kinds = kinds;
+                self.params = params;
+                self.edited();
+                self.list(context);
+                return Ok(false);
+            }
+            Command::Delete(index) => {
+                self.check_index(index)?;
+                self.checkpoint();
+                self.kinds.remove(index);
+                self.params.remove(index);
+                self.edited();
+                self.list(context);
+                return Ok(false);
+            }
+            Command::Replace(index, rank) => {
+                self.check_index(index)?;
+                self.place(Some(index), rank, context)?;
+            }
+            Command::Set(index, param, value) => {
+                self.check_index(index)?;
+                self.checkpoint();
+                self.params[index][param] = value;
+                self.edited();
+                self.list(context);
+                return Ok(false);
+            }
+            Command::Help(_) | Command::Quit | Command::Save(_) | Command::Load(_) => {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    fn check_index(&self, index: usize) -> anyhow::Result<()> {
+        if index >= self.kinds.len() {
+            Err(anyhow!(
+                "no primitive {index}, the program has {}",
+                self.kinds.len()
+            ))?
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) {
+        self.history.push((self.kinds.clone(), self.params.clone()));
+    }
+
+    /// The program changed under the last search, its result no longer applies
+    fn edited(&mut self) {
+        self.ranked.clear();
+        if self.slot.is_some_and(|slot| slot >= self.kinds.len()) {
+            self.slot = None;
+        }
+    }
+
+    fn flat_params(&self) -> Vec<f32> {
+        self.params.iter().flatten().copied().collect()
+    }
+
+    /// The program with the candidate in `slot`, appended for `None`; a
+    /// replaced primitive keeps its operation
+    fn with_candidate(&self, slot: Option<usize>, kind: u8, ps: [f32; 10]) -> (Vec<u8>, Vec<f32>) {
+        let mut kinds = self.kinds.clone();
+        let mut params = self.params.clone();
+        match slot {
+            Some(i) => {
+                let op = params[i][9];
+                kinds[i] = kind;
+                params[i] = ps;
+                params[i][9] = op;
+            }
+            None => {
+                kinds.push(kind);
+                params.push(ps);
+            }
+        }
+        (kinds, params.into_iter().flatten().collect())
+    }
+
+    /// Score of the program up to and without each primitive, `-` where there
+    /// is none, as without the only primitive
+    fn list(&self, context: &Context) {
+        if self.kinds.is_empty() {
+            println!("the program is empty");
+            return;
+        }
+        let score = |kinds: &[u8], params: &[f32]| {
+            if kinds.is_empty() {
+                return "-".to_string();
+            }
+            let candidate = Candidate::new(kinds, params).with_renderer(context.args.renderer);
+            match context.scorer.score(&candidate) {
+                Ok(score) => format!("{score:.4}"),
+                Err(_) => "-".into(),
+            }
+        };
+        let flat = self.flat_params();
+        for (i, (kind, ps)) in self.kinds.iter().zip(&self.params).enumerate() {
+            let op = if i == 0 {
+                ' '
+            } else if ps[9] > 1.0 {
+                '&'
+            } else {
+                '|'
             };
+            let up_to = score(&self.kinds[..=i], &flat[..(i + 1) * 10]);
+            let mut kinds = self.kinds.clone();
+            kinds.remove(i);
+            let without = [&flat[..i * 10], &flat[(i + 1) * 10..]].concat();
+            let without = score(&kinds, &without);
+            let name = repl::KINDS.get(*kind as usize).copied().unwrap_or("?");
+            println!(
+                "{i:>3} {op} {name:<8} {:?}  up to {up_to}, without {without}",
+                &ps[..9]
+            );
+        }
+    }
 
-            let ps = [sx, sy, sz, tx, ty, tz, rx, ry, rz, 0].map(|p| p as f32);
-            println!("{ps:?}");
+    /// Puts the candidate ranked `rank` in `slot` and polishes and prints the
+    /// program; the session is left unchanged when anything fails
+    fn place(&mut self, slot: Option<usize>, rank: usize, context: &Context) -> anyhow::Result<()> {
+        if self.ranked.is_empty() {
+            Err(anyhow!("nothing to commit, search first"))?
+        }
+        // polishing takes a while, and a second Ctrl-C ends the program
+        if let Err(e) = self.autosave(context) {
+            println!("{e}");
+        }
+        let Some(&(kind, ps, score)) = self.ranked.get(rank) else {
+            Err(anyhow!(
+                "no candidate {rank}, the last search kept {}",
+                self.ranked.len()
+            ))?
+        };
+        let args = &context.args;
+        let (kinds, mut params) = self.with_candidate(slot, kind, ps);
+        if let Some(optimizer) = args.polish {
+            let bounds = self.grid.bounds();
+            let config = OptimizeConfig {
+                optimizer,
+                renderer: args.renderer,
+                ..Default::default()
+            };
+            let scorer = context.scorer.as_ref();
+            let (polished, polished_score) = polish(&kinds, &params, scorer, &bounds, &config);
+            println!("polished: {score} -> {polished_score}");
+            params = polished;
+        }
 
-            let mut kinds = built_kinds.clone();
-            kinds.push(kind);
-            let mut params = built_params.clone();
-            params.push(ps);
-            let params = params.into_iter().flatten().collect::<Vec<_>>();
+        emit(&kinds, &params, context)?;
+        self.checkpoint();
+        self.kinds = kinds;
+        self.params = params
+            .chunks_exact(10)
+            .map(|p| p.try_into().unwrap())
+            .collect();
+        self.ranked.clear();
+        self.slot = None;
+        Ok(())
+    }
 
-            let glam = params_to_glam(&kinds, &params);
+    /// Searches the session's ranges for primitive `slot`, or a new one, with
+    /// the rest of the program fixed. The best cells are refined when the
+    /// session asks for it. Ctrl-C stops the search early with the best
+    /// candidates so far and makes it return true.
+    fn search(&mut self, context: &Context) -> bool {
+        let kinds = match self.next {
+            Some(kind) => vec![kind],
+            None => vec![0, 1, 2],
+        };
+        // a second Ctrl-C ends the program without the save on exit
+        if let Err(e) = self.autosave(context) {
+            println!("{e}");
+        }
+        let scored = context.evaluator().search(
+            &self.grid,
+            &self.sampling,
+            &kinds,
+            &self.refine,
+            self.seed.wrapping_add(self.searches),
+            |kind, ps| self.with_candidate(self.slot, kind, *ps),
+            &context.cancel,
+        );
+        self.searches += 1;
+        let cancelled = context.interrupted();
+        if cancelled {
+            println!("search cancelled, keeping the best candidate so far");
+        }
 
-            let score = chamfer_distance(&target_mesh, &glam);
-            visualize(glam.clone(), &rec);
+        self.ranked = search::distinct(&scored, context.args.top.max(1), self.grid.resolution());
+        if self.ranked.is_empty() {
+            println!("no candidate could be scored");
+            return cancelled;
+        }
+        println!("best of {} scored:", scored.len());
+        self.top(context);
+        cancelled
+    }
 
-            if score <= best_candi.0 {
-                best_candi = (score, (glam, (kind, ps)));
+    /// Prints the last search's ranking and shows the best candidate over the
+    /// target and every ranked one in a row beside it
+    fn top(&self, context: &Context) {
+        if self.ranked.is_empty() {
+            println!("no candidates, search first");
+            return;
+        }
+        let _ = context.rec.log("ranked", &rerun::Clear::recursive());
+        let step = Vec3::X * context.extent * 1.5;
+        for (rank, &(kind, ps, score)) in self.ranked.iter().enumerate() {
+            let name = repl::KINDS.get(kind as usize).copied().unwrap_or("?");
+            println!("{rank:>3} {name:<8} {:?} -> {score}", &ps[..9]);
+            let (kinds, params) = self.with_candidate(self.slot, kind, ps);
+            let Ok(mesh) = context.args.renderer.render(&kinds, &params) else {
+                continue;
+            };
+            if rank == 0 {
+                visualize(mesh.positions.clone(), &context.rec);
             }
+            let positions = mesh.positions.iter().map(|p| *p + step * (rank + 1) as f32);
+            let points = rerun::Points3D::new(positions).with_radii([0.1]);
+            let _ = context.rec.log(format!("ranked/{rank}"), &points);
         }
-        let (score, (glam, (kind, ps))) = best_candi;
-        best = (kind, ps, score);
-        visualize(glam.clone(), &rec);
     }
 }
+
+/// Prints a program in the target's frame and units and, outside batch runs
+/// which export once at the end, exports its mesh when asked to
+fn emit(kinds: &[u8], params: &[f32], context: &Context) -> anyhow::Result<()> {
+    let args = &context.args;
+    // one render serves both the registration and the export
+    let exporting = args.export.is_some() && args.batch.is_none();
+    let (mesh, transform) = match context.aligner.is_some() || exporting {
+        true => placed(kinds, params, context)?,
+        false => (Mesh::default(), Rigid::IDENTITY),
+    };
+    let ucad = generate::ucad_unscaled(kinds, params, &transform, &context.scaling)?;
+    println!("{ucad}");
+    if let Some(path) = &args.ucad {
+        std::fs::write(path, &ucad)?;
+    }
+    if exporting {
+        export(&mesh, &transform, context)?;
+    }
+    Ok(())
+}
+
+/// The program's mesh and, when aligning, the rigid transform taking it onto
+/// the target
+fn placed(kinds: &[u8], params: &[f32], context: &Context) -> anyhow::Result<(Mesh, Rigid)> {
+    let mesh = context.args.renderer.render(kinds, params)?;
+    let transform = match &context.aligner {
+        Some(aligner) => {
+            let (transform, error) = aligner.register(&mesh);
+            println!("registered: {transform} (mse {error})");
+            transform
+        }
+        None => Rigid::IDENTITY,
+    };
+    Ok((mesh, transform))
+}
+
+/// Writes `mesh`, moved by `transform` and back in the target's units, to the
+/// `--export` file if there is one
+fn export(mesh: &Mesh, transform: &Rigid, context: &Context) -> anyhow::Result<()> {
+    let Some(path) = &context.args.export else {
+        return Ok(());
+    };
+    let mesh = context.scaling.inverse().mesh(&mesh.transformed(transform));
+    io::save_mesh(path, &mesh)?;
+    println!("exported {}", path.display());
+    Ok(())
+}
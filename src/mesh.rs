@@ -0,0 +1,251 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use clap::ValueEnum;
+use microcad_core::TriangleMesh;
+use rand::prelude::*;
+use rerun::external::glam::{self, Vec3};
+
+use crate::{
+    mesher::{mesh_sdf, SDF_DEPTH},
+    microcad::{generate, Microcad},
+    sdf::ProgramSdf,
+    transform::Rigid,
+};
+
+/// Rendered triangle mesh in glam types, as consumed by the metrics
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl From<TriangleMesh> for Mesh {
+    fn from(mesh: TriangleMesh) -> Self {
+        let positions = mesh
+            .positions
+            .iter()
+            .map(|v| glam::vec3(v.x, v.y, v.z))
+            .collect();
+        let triangles = mesh
+            .triangle_indices
+            .iter()
+            .map(|t| [t.0, t.1, t.2])
+            .collect();
+        Self {
+            positions,
+            triangles,
+        }
+    }
+}
+
+impl Mesh {
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn transformed(&self, transform: &Rigid) -> Self {
+        Self {
+            positions: self.positions.iter().map(|p| transform.apply(*p)).collect(),
+            triangles: self.triangles.clone(),
+        }
+    }
+
+    pub fn triangle(&self, i: usize) -> [Vec3; 3] {
+        self.triangles[i].map(|v| self.positions[v as usize])
+    }
+
+    pub fn triangle_area(&self, i: usize) -> f32 {
+        let [a, b, c] = self.triangle(i);
+        (b - a).cross(c - a).length() * 0.5
+    }
+
+    /// Groups of triangles connected through shared vertices, vertices at the
+    /// same position count as shared
+    pub fn components(&self) -> usize {
+        let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+        let ids: Vec<usize> = self
+            .positions
+            .iter()
+            .map(|p| {
+                let n = welded.len();
+                *welded.entry(p.to_array().map(f32::to_bits)).or_insert(n)
+            })
+            .collect();
+
+        let mut parent: Vec<usize> = (0..welded.len()).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for t in &self.triangles {
+            let a = root(&mut parent, ids[t[0] as usize]);
+            for v in &t[1..] {
+                let b = root(&mut parent, ids[*v as usize]);
+                parent[b] = a;
+            }
+        }
+
+        let mut roots: Vec<usize> = self
+            .triangles
+            .iter()
+            .map(|t| root(&mut parent, ids[t[0] as usize]))
+            .collect();
+        roots.sort_unstable();
+        roots.dedup();
+        roots.len()
+    }
+
+    /// Area-weighted points on the surface, seeded so repeated calls agree
+    pub fn surface_samples(&self, n: usize) -> Vec<Vec3> {
+        if self.triangles.is_empty() {
+            return self.positions.clone();
+        }
+
+        let mut cumulative = Vec::with_capacity(self.triangles.len());
+        let mut total = 0.0;
+        for i in 0..self.triangles.len() {
+            total += self.triangle_area(i);
+            cumulative.push(total);
+        }
+        if total <= 0.0 {
+            return self.positions.clone();
+        }
+
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+        (0..n)
+            .map(|_| {
+                let r = rng.random_range(0.0..total);
+                let i = cumulative
+                    .partition_point(|&c| c <= r)
+                    .min(self.triangles.len() - 1);
+                let [a, b, c] = self.triangle(i);
+                let (mut u, mut v) = (rng.random::<f32>(), rng.random::<f32>());
+                if u + v > 1.0 {
+                    u = 1.0 - u;
+                    v = 1.0 - v;
+                }
+                a + (b - a) * u + (c - a) * v
+            })
+            .collect()
+    }
+}
+
+/// How programs are turned into meshes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Renderer {
+    /// evaluate the generated µcad source
+    #[default]
+    Microcad,
+    /// mesh the analytic distance field, no µcad libraries needed
+    Sdf,
+}
+
+impl Renderer {
+    pub fn render(&self, kinds: &[u8], params: &[f32]) -> anyhow::Result<Mesh> {
+        match self {
+            Renderer::Microcad => params_to_mesh(kinds, params),
+            Renderer::Sdf => Ok(mesh_sdf(&ProgramSdf::new(kinds, params)?, SDF_DEPTH)),
+        }
+    }
+}
+
+thread_local! {
+    /// µcad engines can't be shared between threads, so every thread renders
+    /// with its own and keeps its render cache from one program to the next
+    static ENGINE: RefCell<Microcad> = RefCell::new(Microcad::new());
+}
+
+pub fn params_to_mesh(kinds: &[u8], params: &[f32]) -> anyhow::Result<Mesh> {
+    let ucad = generate::ucad(kinds, params)?;
+    ENGINE.with_borrow_mut(|engine| {
+        engine.set_root(&ucad);
+        Ok(engine.render_mesh()?.into())
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Closed, outward wound box from `min` to `max`
+    pub(crate) fn cuboid(min: Vec3, max: Vec3) -> Mesh {
+        let positions = (0..8)
+            .map(|i| {
+                Vec3::new(
+                    if i & 1 == 0 { min.x } else { max.x },
+                    if i & 2 == 0 { min.y } else { max.y },
+                    if i & 4 == 0 { min.z } else { max.z },
+                )
+            })
+            .collect();
+        let triangles = vec![
+            [0, 4, 6],
+            [0, 6, 2],
+            [1, 3, 7],
+            [1, 7, 5],
+            [0, 1, 5],
+            [0, 5, 4],
+            [2, 6, 7],
+            [2, 7, 3],
+            [0, 2, 3],
+            [0, 3, 1],
+            [4, 5, 7],
+            [4, 7, 6],
+        ];
+        Mesh {
+            positions,
+            triangles,
+        }
+    }
+
+    pub(crate) fn unit_cube() -> Mesh {
+        cuboid(Vec3::ZERO, Vec3::ONE)
+    }
+
+    #[test]
+    fn cuboid_faces_point_outwards() {
+        let mesh = unit_cube();
+        let center = Vec3::splat(0.5);
+        for i in 0..mesh.triangles.len() {
+            let [a, b, c] = mesh.triangle(i);
+            let normal = (b - a).cross(c - a);
+            assert!(normal.dot((a + b + c) / 3.0 - center) > 0.0, "triangle {i}");
+        }
+    }
+
+    #[test]
+    fn components_weld_shared_corners() {
+        let mut mesh = unit_cube();
+        assert_eq!(mesh.components(), 1);
+        // a second box touching the first at a corner, with its own vertices
+        let other = cuboid(Vec3::ONE, Vec3::splat(2.0));
+        let base = mesh.positions.len() as u32;
+        mesh.positions.extend(&other.positions);
+        mesh.triangles
+            .extend(other.triangles.iter().map(|t| t.map(|v| v + base)));
+        assert_eq!(mesh.components(), 1);
+
+        let far = cuboid(Vec3::splat(5.0), Vec3::splat(6.0));
+        let base = mesh.positions.len() as u32;
+        mesh.positions.extend(&far.positions);
+        mesh.triangles
+            .extend(far.triangles.iter().map(|t| t.map(|v| v + base)));
+        assert_eq!(mesh.components(), 2);
+        assert_eq!(Mesh::default().components(), 0);
+    }
+
+    #[test]
+    fn surface_samples_lie_on_the_surface_and_repeat() {
+        let mesh = unit_cube();
+        let samples = mesh.surface_samples(100);
+        assert_eq!(samples.len(), 100);
+        assert_eq!(samples, mesh.surface_samples(100));
+        for p in samples {
+            let to_face = p.min_element().min((Vec3::ONE - p).min_element());
+            assert!(to_face.abs() < 1e-5, "{p}");
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use rerun::external::glam::{IVec3, Vec3};
+
+use crate::{mesh::Mesh, sdf::ProgramSdf};
+
+/// Octree depth used when rendering candidates, 64 cells along the longest side
+pub const SDF_DEPTH: u32 = 6;
+
+/// Grid corners and cells of an octree of depth `depth` over a cube, corner
+/// values are cached since neighbouring cells share them
+struct Grid<'a> {
+    sdf: &'a ProgramSdf,
+    origin: Vec3,
+    cell: f32,
+    values: HashMap<IVec3, f32>,
+}
+
+impl Grid<'_> {
+    fn point(&self, corner: IVec3) -> Vec3 {
+        self.origin + corner.as_vec3() * self.cell
+    }
+
+    fn value(&mut self, corner: IVec3) -> f32 {
+        if let Some(v) = self.values.get(&corner) {
+            return *v;
+        }
+        let v = self.sdf.distance(self.point(corner));
+        self.values.insert(corner, v);
+        v
+    }
+
+    /// Finest cells the surface may pass through; min/max CSG never
+    /// overestimates the distance, so a node whose centre is further from the
+    /// surface than its half diagonal can be dropped with everything below it
+    fn collect(&self, min: IVec3, size: i32, cells: &mut Vec<IVec3>) {
+        let center = self.origin + (min.as_vec3() + size as f32 * 0.5) * self.cell;
+        let half_diagonal = size as f32 * self.cell * 3f32.sqrt() * 0.5;
+        if self.sdf.distance(center).abs() > half_diagonal * 1.001 {
+            return;
+        }
+        if size == 1 {
+            cells.push(min);
+            return;
+        }
+        let half = size / 2;
+        for child in 0..8 {
+            let offset = IVec3::new(child & 1, (child >> 1) & 1, (child >> 2) & 1) * half;
+            self.collect(min + offset, half, cells);
+        }
+    }
+}
+
+const CORNERS: [IVec3; 8] = [
+    IVec3::new(0, 0, 0),
+    IVec3::new(1, 0, 0),
+    IVec3::new(0, 1, 0),
+    IVec3::new(1, 1, 0),
+    IVec3::new(0, 0, 1),
+    IVec3::new(1, 0, 1),
+    IVec3::new(0, 1, 1),
+    IVec3::new(1, 1, 1),
+];
+
+/// Pairs of [`CORNERS`] joined by a cell edge
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// Surface nets over an adaptive octree: the field is only sampled near the
+/// surface, each cell the surface crosses gets the mean of its edge crossings
+/// as a vertex and every crossed grid edge becomes a quad of the four cells
+/// around it. Triangles wind counter-clockwise seen from outside.
+pub fn mesh_sdf(sdf: &ProgramSdf, depth: u32) -> Mesh {
+    if sdf.is_empty() {
+        return Mesh::default();
+    }
+    let bounds = sdf.bounds();
+    let resolution = 1i32 << depth.min(10);
+    // a cell of margin keeps the surface off the grid boundary
+    let side = bounds.size().max_element().max(f32::EPSILON);
+    let cell = side / (resolution - 2).max(1) as f32;
+    let origin = bounds.center() - Vec3::splat(cell * resolution as f32 * 0.5);
+    let mut grid = Grid {
+        sdf,
+        origin,
+        cell,
+        values: HashMap::new(),
+    };
+
+    let mut cells = vec![];
+    grid.collect(IVec3::ZERO, resolution, &mut cells);
+
+    let mut mesh = Mesh::default();
+    let mut vertices: HashMap<IVec3, u32> = HashMap::new();
+    for &c in &cells {
+        let values = CORNERS.map(|corner| grid.value(c + corner));
+        let mut sum = Vec3::ZERO;
+        let mut crossings = 0;
+        for (a, b) in EDGES {
+            let (va, vb) = (values[a], values[b]);
+            if (va <= 0.0) != (vb <= 0.0) {
+                let t = va / (va - vb);
+                let (pa, pb) = (grid.point(c + CORNERS[a]), grid.point(c + CORNERS[b]));
+                sum += pa + (pb - pa) * t;
+                crossings += 1;
+            }
+        }
+        if crossings > 0 {
+            vertices.insert(c, mesh.positions.len() as u32);
+            mesh.positions.push(sum / crossings as f32);
+        }
+    }
+
+    let mut active: Vec<IVec3> = vertices.keys().copied().collect();
+    active.sort_unstable_by_key(|c| c.to_array());
+    for c in active {
+        let start = grid.value(c);
+        for axis in 0..3 {
+            let (e, u, v) = (unit(axis), unit((axis + 1) % 3), unit((axis + 2) % 3));
+            let end = grid.value(c + e);
+            if (start <= 0.0) == (end <= 0.0) {
+                continue;
+            }
+            let quad = [c, c - u, c - u - v, c - v].map(|cell| vertices.get(&cell).copied());
+            let [Some(a), Some(b), Some(d), Some(f)] = quad else {
+                continue;
+            };
+            // inside at the start of the edge means the surface faces along it
+            if start <= 0.0 {
+                mesh.triangles.extend([[a, b, d], [a, d, f]]);
+            } else {
+                mesh.triangles.extend([[a, d, b], [a, f, d]]);
+            }
+        }
+    }
+    mesh
+}
+
+fn unit(axis: usize) -> IVec3 {
+    let mut e = IVec3::ZERO;
+    e[axis] = 1;
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::Moments,
+        mesh::params_to_mesh,
+        volume::{is_watertight, voxel_iou},
+    };
+
+    const SPHERE: [f32; 10] = [1.5, 0.0, 0.0, 1.0, 2.0, 3.0, 0.0, 0.0, 0.0, 0.0];
+
+    #[test]
+    fn sphere_is_closed_and_on_the_surface() {
+        let sdf = ProgramSdf::new(&[1], &SPHERE).unwrap();
+        let mesh = mesh_sdf(&sdf, 5);
+        assert!(is_watertight(&mesh));
+        assert_eq!(mesh.components(), 1);
+        // vertices stay within the cell the surface crosses
+        let cell = 3.0 / 30.0;
+        for p in &mesh.positions {
+            assert!(sdf.distance(*p).abs() < cell * 3f32.sqrt(), "{p}");
+        }
+        // outward winding gives a positive volume
+        let volume = Moments::new(&mesh).volume;
+        let exact = 4.0 / 3.0 * std::f32::consts::PI * 1.5f32.powi(3);
+        assert!((volume - exact).abs() < exact * 0.05, "{volume} {exact}");
+    }
+
+    #[test]
+    fn empty_programs_have_no_mesh() {
+        let mesh = mesh_sdf(&ProgramSdf::default(), SDF_DEPTH);
+        assert!(mesh.is_empty() && mesh.triangles.is_empty());
+    }
+
+    #[test]
+    fn matches_the_microcad_render() {
+        let cube = [2.0, 1.0, 3.0, 0.5, 0.0, 0.0, 20.0, 0.0, 45.0, 0.0];
+        let cylinder = [1.0, 2.5, 0.0, 0.0, 1.0, 0.0, 0.0, 30.0, 0.0, 0.0];
+        for (kinds, params) in [
+            (vec![1], SPHERE.to_vec()),
+            (vec![0, 2], [cube, cylinder].concat()),
+            (vec![0, 1], [cube, SPHERE].concat()),
+        ] {
+            let sdf = ProgramSdf::new(&kinds, &params).unwrap();
+            let meshed = mesh_sdf(&sdf, SDF_DEPTH);
+            let rendered = params_to_mesh(&kinds, &params).unwrap();
+            let iou = voxel_iou(&rendered, &meshed, 64).unwrap();
+            assert!(iou > 0.9, "{kinds:?}: {iou}");
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use rerun::external::glam::Vec3;
+
+use crate::{bvh::Bvh, mesh::Mesh};
+
+/// Points sampled per mesh for the surface distance
+pub const SURFACE_SAMPLES: usize = 2048;
+/// Level set samples per candidate for the way back of the SDF scorer
+pub const SDF_SAMPLES: usize = 512;
+
+/// Mesh metrics available as [`crate::score::Scorer`]s, lower is better
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Metric {
+    /// vertex to nearest vertex
+    #[default]
+    Chamfer,
+    /// surface samples to nearest triangle
+    Surface,
+    /// one minus voxelized intersection over union
+    Iou,
+    /// one minus intersection over union from inside/outside samples
+    SampledIou,
+    /// analytic signed distance of the target points, nothing is rendered
+    Sdf,
+}
+
+/// Index of the point of `to` nearest to `p` and its squared distance
+pub fn nearest(p: Vec3, to: &[Vec3]) -> Option<(usize, f32)> {
+    let mut best = None;
+    let mut best_d = f32::INFINITY;
+    for (i, q) in to.iter().enumerate() {
+        let d = (p - *q).length_squared();
+        if d < best_d {
+            best_d = d;
+            best = Some((i, d));
+        }
+    }
+    best
+}
+
+/// Mean squared distance from each point of `from` to its nearest point of `to`
+pub fn nearest_sum(from: &[Vec3], to: &[Vec3]) -> f32 {
+    let mut accum = 0.0;
+    for p in from {
+        accum += nearest(*p, to).map_or(f32::INFINITY, |(_, d)| d);
+    }
+    accum / from.len() as f32
+}
+
+pub fn chamfer_distance(a: &[Vec3], b: &[Vec3]) -> f32 {
+    nearest_sum(a, b) + nearest_sum(b, a)
+}
+
+/// How a squared nearest distance contributes to a term
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Loss {
+    #[default]
+    Squared,
+    /// squared up to `delta`, linear beyond, so outliers pull less
+    Huber(f32),
+    /// squared, capped at `tau` so outliers stop mattering at all
+    Truncated(f32),
+}
+
+impl Loss {
+    pub fn apply(&self, d2: f32) -> f32 {
+        match *self {
+            Loss::Squared => d2,
+            Loss::Huber(delta) => {
+                let d = d2.sqrt();
+                if d <= delta {
+                    d2
+                } else {
+                    2.0 * delta * d - delta * delta
+                }
+            }
+            Loss::Truncated(tau) => d2.min(tau * tau),
+        }
+    }
+
+    /// Derivative with respect to the squared distance
+    pub fn derivative(&self, d2: f32) -> f32 {
+        match *self {
+            Loss::Squared => 1.0,
+            Loss::Huber(delta) => {
+                let d = d2.sqrt();
+                if d <= delta {
+                    1.0
+                } else {
+                    delta / d
+                }
+            }
+            Loss::Truncated(tau) => (d2 < tau * tau) as u8 as f32,
+        }
+    }
+}
+
+/// How per-point losses are pooled into one number
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Reduce {
+    #[default]
+    Mean,
+    /// mean of the smallest fraction, drops the worst points
+    Trimmed(f32),
+    /// the loss at a quantile in `0..=1`
+    Percentile(f32),
+}
+
+impl Reduce {
+    /// Weights `w` such that the pooled value is `sum(w[i] * values[i])`
+    pub fn weights(&self, values: &[f32]) -> Vec<f32> {
+        let n = values.len();
+        if n == 0 {
+            return vec![];
+        }
+
+        let order = || {
+            let mut order: Vec<usize> = (0..n).collect();
+            order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+            order
+        };
+
+        let mut weights = vec![0.0; n];
+        match *self {
+            Reduce::Mean => weights.fill(1.0 / n as f32),
+            Reduce::Trimmed(keep) => {
+                let k = ((keep * n as f32).ceil() as usize).clamp(1, n);
+                for i in &order()[..k] {
+                    weights[*i] = 1.0 / k as f32;
+                }
+            }
+            Reduce::Percentile(q) => {
+                let i = (q.clamp(0.0, 1.0) * (n - 1) as f32).round() as usize;
+                weights[order()[i]] = 1.0;
+            }
+        }
+        weights
+    }
+
+    pub fn apply(&self, values: &[f32]) -> f32 {
+        if values.is_empty() {
+            return f32::INFINITY;
+        }
+        self.weights(values)
+            .iter()
+            .zip(values)
+            .map(|(w, v)| w * v)
+            .sum()
+    }
+}
+
+/// Robust variant of a two-sided distance for noisy and partial targets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Robust {
+    pub loss: Loss,
+    pub reduce: Reduce,
+    /// weight of the target → candidate term, how well what is seen is explained
+    pub forward: f32,
+    /// weight of the candidate → target term, lower it when the scan has holes
+    pub backward: f32,
+}
+
+impl Default for Robust {
+    fn default() -> Self {
+        Self {
+            loss: Loss::Squared,
+            reduce: Reduce::Mean,
+            forward: 1.0,
+            backward: 1.0,
+        }
+    }
+}
+
+impl Robust {
+    /// Pools squared distances of one direction
+    pub fn pool(&self, d2: &[f32]) -> f32 {
+        let losses: Vec<f32> = d2.iter().map(|d| self.loss.apply(*d)).collect();
+        self.reduce.apply(&losses)
+    }
+
+    /// `d(pool)/d(d2[i])` for every point
+    pub fn pool_derivative(&self, d2: &[f32]) -> Vec<f32> {
+        let losses: Vec<f32> = d2.iter().map(|d| self.loss.apply(*d)).collect();
+        self.reduce
+            .weights(&losses)
+            .into_iter()
+            .zip(d2)
+            .map(|(w, d)| w * self.loss.derivative(*d))
+            .collect()
+    }
+}
+
+pub fn nearest_distances(from: &[Vec3], to: &[Vec3]) -> Vec<f32> {
+    from.iter()
+        .map(|p| nearest(*p, to).map_or(f32::INFINITY, |(_, d)| d))
+        .collect()
+}
+
+/// Chamfer distance with robust losses, trimming and asymmetric weights
+pub fn robust_chamfer(target: &[Vec3], candidate: &[Vec3], robust: &Robust) -> f32 {
+    robust.forward * robust.pool(&nearest_distances(target, candidate))
+        + robust.backward * robust.pool(&nearest_distances(candidate, target))
+}
+
+/// Mean squared distance from each point of `from` to the surface in `to`
+pub fn point_to_surface(from: &[Vec3], to: &Bvh) -> f32 {
+    if from.is_empty() || to.is_empty() {
+        return f32::INFINITY;
+    }
+    from.iter().map(|p| to.distance_squared(*p)).sum::<f32>() / from.len() as f32
+}
+
+/// Symmetric point-to-triangle distance between two meshes, so coarse but exact
+/// tessellations are not penalised for having few vertices
+pub fn surface_distance(a: &Mesh, b: &Mesh) -> f32 {
+    one_sided(a, b, &Bvh::new(b)) + one_sided(b, a, &Bvh::new(a))
+}
+
+fn one_sided(from: &Mesh, to: &Mesh, to_bvh: &Bvh) -> f32 {
+    let samples = from.surface_samples(SURFACE_SAMPLES);
+    if to_bvh.is_empty() {
+        // point cloud on the other side, nothing to project onto
+        return nearest_sum(&samples, &to.positions);
+    }
+    point_to_surface(&samples, to_bvh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-5 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn huber_is_quadratic_then_linear() {
+        let huber = Loss::Huber(1.0);
+        assert_eq!(huber.apply(0.25), 0.25);
+        assert!(close(huber.apply(4.0), 3.0));
+        assert!(close(huber.apply(1.0), 1.0));
+        assert_eq!(huber.derivative(0.25), 1.0);
+        assert!(close(huber.derivative(4.0), 0.5));
+    }
+
+    #[test]
+    fn truncated_caps_the_loss() {
+        let truncated = Loss::Truncated(1.0);
+        assert_eq!(truncated.apply(0.25), 0.25);
+        assert_eq!(truncated.apply(9.0), 1.0);
+        assert_eq!(truncated.derivative(0.25), 1.0);
+        assert_eq!(truncated.derivative(9.0), 0.0);
+    }
+
+    #[test]
+    fn reductions() {
+        let values = [1.0, 100.0, 2.0, 3.0];
+        assert!(close(Reduce::Mean.apply(&values), 26.5));
+        assert!(close(Reduce::Trimmed(0.5).apply(&values), 1.5));
+        assert_eq!(Reduce::Percentile(0.0).apply(&values), 1.0);
+        assert_eq!(Reduce::Percentile(1.0).apply(&values), 100.0);
+        assert_eq!(Reduce::Percentile(0.5).apply(&[5.0, 1.0, 3.0]), 3.0);
+        assert_eq!(Reduce::Mean.apply(&[]), f32::INFINITY);
+        for reduce in [Reduce::Mean, Reduce::Trimmed(0.3), Reduce::Percentile(0.7)] {
+            assert!(
+                close(reduce.weights(&values).iter().sum(), 1.0),
+                "{reduce:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn pool_derivative_matches_finite_differences() {
+        let robust = Robust {
+            loss: Loss::Huber(1.0),
+            ..Default::default()
+        };
+        let d2 = [0.25, 4.0, 9.0];
+        let h = 1e-3;
+        for (i, derivative) in robust.pool_derivative(&d2).into_iter().enumerate() {
+            let mut up = d2;
+            up[i] += h;
+            let mut down = d2;
+            down[i] -= h;
+            let numeric = (robust.pool(&up) - robust.pool(&down)) / (2.0 * h);
+            assert!(
+                (numeric - derivative).abs() < 1e-3,
+                "{i}: {numeric} {derivative}"
+            );
+        }
+    }
+
+    #[test]
+    fn robust_chamfer_defaults_to_chamfer() {
+        let a = [Vec3::ZERO, Vec3::X, Vec3::Y];
+        let b = [Vec3::new(0.1, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0)];
+        let robust = robust_chamfer(&a, &b, &Robust::default());
+        assert!(close(robust, chamfer_distance(&a, &b)));
+    }
+
+    #[test]
+    fn backward_weight_ignores_unseen_geometry() {
+        let target = [Vec3::ZERO, Vec3::X];
+        let candidate = [Vec3::ZERO, Vec3::X, Vec3::new(10.0, 0.0, 0.0)];
+        let partial = Robust {
+            backward: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(robust_chamfer(&target, &candidate, &partial), 0.0);
+        assert!(robust_chamfer(&target, &candidate, &Robust::default()) > 0.0);
+    }
+}
@@ -3,9 +3,33 @@ use std::io::Write as _;
 use anyhow::anyhow;
 use rand::prelude::*;
 
-use crate::microcad::PRELUDE;
+use crate::{
+    microcad::PRELUDE,
+    transform::{Rigid, Scaling},
+};
 
 pub fn ucad(tokens: &[u8], params: &[f32]) -> anyhow::Result<String> {
+    ucad_transformed(tokens, params, &Rigid::IDENTITY)
+}
+
+/// Like [`ucad_transformed`] for a program found against a target moved by
+/// `scaling`, emitted in the target's original units
+pub fn ucad_unscaled(
+    tokens: &[u8],
+    params: &[f32],
+    transform: &Rigid,
+    scaling: &Scaling,
+) -> anyhow::Result<String> {
+    let (params, transform) = scaling.unscale_program(params, transform);
+    ucad_transformed(tokens, &params, &transform)
+}
+
+/// Like [`ucad`], with `transform` applied to the whole combined object
+pub fn ucad_transformed(
+    tokens: &[u8],
+    params: &[f32],
+    transform: &Rigid,
+) -> anyhow::Result<String> {
     // assert_eq!(token.len(), params.len());
     if tokens.len() * 10 != params.len() {
         Err(anyhow!(format!(
@@ -23,14 +47,13 @@ pub fn ucad(tokens: &[u8], params: &[f32]) -> anyhow::Result<String> {
 
     writeln!(ucad, "{}", PRELUDE)?;
 
-    for (p, params) in tokens.into_iter().zip(params.chunks_exact(10)) {
+    for (p, params) in tokens.iter().zip(params.chunks_exact(10)) {
         if let [sx, sy, sz, px, py, pz, rx, ry, rz, sig] = params {
             let name: String = (&mut rng)
                 .sample_iter(rand::distr::Alphabetic)
                 .take(10)
                 .map(char::from)
                 .collect();
-            objs.push((name.clone(), *sig <= 1.0));
 
             match p {
                 0 => {
@@ -54,6 +77,8 @@ pub fn ucad(tokens: &[u8], params: &[f32]) -> anyhow::Result<String> {
                 4 => break,
                 _ => Err(anyhow!(format!("invalid token: {p}")))?,
             }
+            // only primitives that were written are combined
+            objs.push((name, *sig <= 1.0));
             writeln!(ucad, "\t.translate(x = {px}mm, y = {py}mm, z = {pz}mm)")?;
             writeln!(ucad, "\t.rotate(x = {rx}deg, y = {ry}deg, z = {rz}deg);")?;
         } else {
@@ -61,7 +86,14 @@ pub fn ucad(tokens: &[u8], params: &[f32]) -> anyhow::Result<String> {
         }
     }
 
-    write!(ucad, "{}", objs[0].0)?;
+    let Some((first, _)) = objs.first() else {
+        Err(anyhow!("the program has no objects"))?
+    };
+    let transformed = !transform.is_identity();
+    if transformed {
+        write!(ucad, "(")?;
+    }
+    write!(ucad, "{first}")?;
     for obj in objs.iter().skip(1) {
         if obj.1 {
             write!(ucad, " | ")?;
@@ -70,9 +102,51 @@ pub fn ucad(tokens: &[u8], params: &[f32]) -> anyhow::Result<String> {
         }
         write!(ucad, "{}", obj.0)?;
     }
+    if transformed {
+        write!(ucad, ").{transform}")?;
+    }
     writeln!(ucad, ";")?;
 
     let var_name = String::from_utf8(ucad)?;
     // println!("{var_name}");
     Ok(var_name)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rerun::external::glam::Vec3;
+
+    const CUBE: [f32; 10] = [1.0, 2.0, 3.0, 0.5, 0.0, 0.0, 0.0, 0.0, 90.0, 0.0];
+    const SPHERE: [f32; 10] = [2.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0];
+
+    #[test]
+    fn primitives_are_combined_by_their_op() {
+        let ucad = ucad(&[0, 1], &[CUBE, SPHERE].concat()).unwrap();
+        assert!(ucad.contains("= Cube(size_x = 1mm, size_y = 2mm, size_z = 3mm)"));
+        assert!(ucad.contains("\t.translate(x = 0.5mm, y = 0mm, z = 0mm)"));
+        assert!(ucad.contains("\t.rotate(x = 0deg, y = 0deg, z = 90deg);"));
+        assert!(ucad.contains("= Sphere(2mm)"));
+        let last = ucad.lines().last().unwrap();
+        assert!(last.contains(" & ") && last.ends_with(';'), "{last}");
+    }
+
+    #[test]
+    fn transforms_wrap_the_whole_object() {
+        let transform = Rigid {
+            translation: Vec3::X,
+            ..Rigid::IDENTITY
+        };
+        let ucad = ucad_transformed(&[0], &CUBE, &transform).unwrap();
+        let last = ucad.lines().last().unwrap();
+        assert!(last.starts_with('(') && last.contains(")."), "{last}");
+    }
+
+    #[test]
+    fn programs_without_objects_are_errors() {
+        assert!(ucad(&[], &[]).is_err());
+        assert!(ucad(&[4], &CUBE).is_err());
+        assert!(ucad(&[0], &CUBE[..9]).is_err());
+        assert!(ucad(&[7], &CUBE).is_err());
+    }
+}
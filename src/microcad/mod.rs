@@ -1,4 +1,7 @@
-use std::{path::PathBuf, rc::Rc};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use anyhow::anyhow;
 use microcad_builtin::*;
@@ -54,6 +57,20 @@ impl Microcad {
         self.root = SourceFile::load_from_str(None, "tmp", &n).unwrap();
     }
 
+    /// Uses a µcad file as the root, as written; its directory is searched for
+    /// the modules it uses before the configured library paths
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        self.root =
+            SourceFile::load(path).map_err(|e| anyhow!("cannot load {}: {e}", path.display()))?;
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            if !self.lib_paths.iter().any(|p| p == dir) {
+                self.lib_paths.insert(0, dir.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
     pub fn render_mesh(&mut self) -> anyhow::Result<TriangleMesh> {
         let res_ctx = ResolveContext::create(
             self.root.clone(),
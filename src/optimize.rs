@@ -0,0 +1,477 @@
+use std::collections::VecDeque;
+
+use crate::{
+    mesh::{Mesh, Renderer},
+    score::{Candidate, Scorer},
+};
+
+/// Ranges a primitive's parameters may be polished within, the defaults
+/// match [`crate::generate_random`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParamBounds {
+    pub size: (f32, f32),
+    pub translation: (f32, f32),
+    pub rotation: (f32, f32),
+}
+
+impl Default for ParamBounds {
+    fn default() -> Self {
+        Self {
+            size: (1.0, 20.0),
+            translation: (0.0, 5.0),
+            rotation: (0.0, 360.0),
+        }
+    }
+}
+
+impl ParamBounds {
+    /// Per parameter `(low, high)` for a program, widened to contain `params`;
+    /// parameters a kind ignores and the boolean op are pinned to their value
+    pub fn for_program(&self, kinds: &[u8], params: &[f32]) -> Vec<(f32, f32)> {
+        let mut bounds = Vec::with_capacity(params.len());
+        for (kind, params) in kinds.iter().zip(params.chunks_exact(10)) {
+            let sizes = match kind {
+                0 => 3,
+                1 => 1,
+                2 => 2,
+                _ => 0,
+            };
+            let movable = *kind <= 2;
+            for (i, p) in params.iter().enumerate() {
+                let range = match i {
+                    0..3 if i < sizes => Some(self.size),
+                    3..6 if movable => Some(self.translation),
+                    6..9 if movable => Some(self.rotation),
+                    _ => None,
+                };
+                bounds.push(match range {
+                    Some((low, high)) => (low.min(*p), high.max(*p)),
+                    None => (*p, *p),
+                });
+            }
+        }
+        bounds
+    }
+}
+
+/// Something to minimise over a box
+pub trait Objective {
+    fn value(&mut self, x: &[f32]) -> f32;
+
+    /// Central differences by default
+    fn gradient(&mut self, x: &[f32], fx: f32, bounds: &[(f32, f32)], step: f32) -> Vec<f32> {
+        finite_difference(self, x, fx, bounds, step)
+    }
+}
+
+/// Scores a program with varying parameters and fixed kinds
+pub struct ProgramObjective<'a> {
+    pub kinds: &'a [u8],
+    pub scorer: &'a dyn Scorer,
+    pub renderer: Renderer,
+    pub evaluations: usize,
+}
+
+impl<'a> ProgramObjective<'a> {
+    pub fn new(kinds: &'a [u8], scorer: &'a dyn Scorer) -> Self {
+        Self {
+            kinds,
+            scorer,
+            renderer: Renderer::default(),
+            evaluations: 0,
+        }
+    }
+
+    fn render(&self, x: &[f32]) -> Option<Mesh> {
+        self.renderer.render(self.kinds, x).ok()
+    }
+}
+
+impl Objective for ProgramObjective<'_> {
+    fn value(&mut self, x: &[f32]) -> f32 {
+        self.evaluations += 1;
+        self.scorer
+            .score(&Candidate::new(self.kinds, x).with_renderer(self.renderer))
+            .unwrap_or(f32::INFINITY)
+    }
+
+    /// Chains the scorer's vertex gradient with finite differences of the
+    /// rendered vertices when the scorer has one and the tessellation keeps
+    /// its vertex count, which costs renders but no extra scoring; falls back
+    /// to differencing the score otherwise
+    fn gradient(&mut self, x: &[f32], fx: f32, bounds: &[(f32, f32)], step: f32) -> Vec<f32> {
+        let analytic = self.render(x).and_then(|mesh| {
+            let candidate = Candidate::with_mesh(self.kinds, x, mesh);
+            let grad = self.scorer.gradient(&candidate).ok().flatten()?;
+            Some((candidate.into_mesh().ok()?, grad))
+        });
+        let Some((mesh, vertex_grad)) = analytic else {
+            return finite_difference(self, x, fx, bounds, step);
+        };
+
+        let mut grad = vec![0.0; x.len()];
+        let mut probe = x.to_vec();
+        for i in 0..x.len() {
+            let (low, high) = bounds[i];
+            if high <= low {
+                continue;
+            }
+            let h = step * (high - low);
+            probe[i] = if x[i] + h <= high { x[i] + h } else { x[i] - h };
+            let moved = self.render(&probe);
+            let dx = probe[i] - x[i];
+            probe[i] = x[i];
+
+            match moved {
+                Some(moved) if moved.positions.len() == mesh.positions.len() => {
+                    grad[i] = vertex_grad
+                        .iter()
+                        .zip(moved.positions.iter().zip(&mesh.positions))
+                        .map(|(g, (a, b))| g.dot((*a - *b) / dx))
+                        .sum();
+                }
+                _ => return finite_difference(self, x, fx, bounds, step),
+            }
+        }
+        grad
+    }
+}
+
+/// Central differences with steps of `step` times each range, one sided at
+/// the bounds; pinned dimensions get a zero gradient
+pub fn finite_difference<O: Objective + ?Sized>(
+    objective: &mut O,
+    x: &[f32],
+    fx: f32,
+    bounds: &[(f32, f32)],
+    step: f32,
+) -> Vec<f32> {
+    let mut grad = vec![0.0; x.len()];
+    let mut probe = x.to_vec();
+    for i in 0..x.len() {
+        let (low, high) = bounds[i];
+        if high <= low {
+            continue;
+        }
+        let h = step * (high - low);
+        let up = (x[i] + h).min(high);
+        let down = (x[i] - h).max(low);
+
+        probe[i] = up;
+        let f_up = if up > x[i] {
+            objective.value(&probe)
+        } else {
+            fx
+        };
+        probe[i] = down;
+        let f_down = if down < x[i] {
+            objective.value(&probe)
+        } else {
+            fx
+        };
+        probe[i] = x[i];
+
+        if up > down {
+            grad[i] = (f_up - f_down) / (up - down);
+        }
+    }
+    grad
+}
+
+fn project(x: &mut [f32], bounds: &[(f32, f32)]) {
+    for (x, (low, high)) in x.iter_mut().zip(bounds) {
+        *x = x.clamp(*low, *high);
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Optimizer {
+    #[default]
+    Adam,
+    Lbfgs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OptimizeConfig {
+    pub optimizer: Optimizer,
+    pub iterations: usize,
+    /// finite difference step as a fraction of each parameter's range
+    pub step: f32,
+    /// Adam learning rate as a fraction of each parameter's range
+    pub learning_rate: f32,
+    /// remembered curvature pairs for L-BFGS
+    pub memory: usize,
+    /// relative improvement below which the search stops
+    pub tolerance: f32,
+    pub renderer: Renderer,
+}
+
+impl Default for OptimizeConfig {
+    fn default() -> Self {
+        Self {
+            optimizer: Optimizer::Adam,
+            iterations: 50,
+            step: 0.01,
+            learning_rate: 0.02,
+            memory: 6,
+            tolerance: 1e-4,
+            renderer: Renderer::default(),
+        }
+    }
+}
+
+/// Minimises `objective` from `x0` within `bounds`, returning the best point seen
+pub fn minimize(
+    objective: &mut impl Objective,
+    x0: &[f32],
+    bounds: &[(f32, f32)],
+    config: &OptimizeConfig,
+) -> (Vec<f32>, f32) {
+    match config.optimizer {
+        Optimizer::Adam => adam(objective, x0, bounds, config),
+        Optimizer::Lbfgs => lbfgs(objective, x0, bounds, config),
+    }
+}
+
+/// Adam on parameters scaled to their ranges, projected back into the box
+pub fn adam(
+    objective: &mut impl Objective,
+    x0: &[f32],
+    bounds: &[(f32, f32)],
+    config: &OptimizeConfig,
+) -> (Vec<f32>, f32) {
+    const BETA1: f32 = 0.9;
+    const BETA2: f32 = 0.999;
+    const EPSILON: f32 = 1e-8;
+
+    let mut x = x0.to_vec();
+    project(&mut x, bounds);
+    let mut fx = objective.value(&x);
+    let mut best = (x.clone(), fx);
+
+    let mut m = vec![0.0; x.len()];
+    let mut v = vec![0.0; x.len()];
+    let mut stalled = 0;
+
+    for t in 1..=config.iterations {
+        let grad = objective.gradient(&x, fx, bounds, config.step);
+        for i in 0..x.len() {
+            let width = bounds[i].1 - bounds[i].0;
+            // gradient with respect to the parameter scaled to 0..=1
+            let g = grad[i] * width;
+            m[i] = BETA1 * m[i] + (1.0 - BETA1) * g;
+            v[i] = BETA2 * v[i] + (1.0 - BETA2) * g * g;
+            let m_hat = m[i] / (1.0 - BETA1.powi(t as i32));
+            let v_hat = v[i] / (1.0 - BETA2.powi(t as i32));
+            x[i] -= config.learning_rate * width * m_hat / (v_hat.sqrt() + EPSILON);
+        }
+        project(&mut x, bounds);
+        fx = objective.value(&x);
+
+        if fx < best.1 {
+            let improvement = (best.1 - fx) / best.1.abs().max(f32::EPSILON);
+            best = (x.clone(), fx);
+            stalled = if improvement < config.tolerance {
+                stalled + 1
+            } else {
+                0
+            };
+        } else {
+            stalled += 1;
+        }
+        if stalled >= 10 {
+            break;
+        }
+    }
+
+    best
+}
+
+/// Projected L-BFGS with a backtracking line search
+pub fn lbfgs(
+    objective: &mut impl Objective,
+    x0: &[f32],
+    bounds: &[(f32, f32)],
+    config: &OptimizeConfig,
+) -> (Vec<f32>, f32) {
+    let n = x0.len();
+    let mut x = x0.to_vec();
+    project(&mut x, bounds);
+    let mut fx = objective.value(&x);
+    let mut grad = objective.gradient(&x, fx, bounds, config.step);
+    let mut history: VecDeque<(Vec<f32>, Vec<f32>, f32)> = VecDeque::new();
+
+    for _ in 0..config.iterations {
+        // two loop recursion for the quasi-Newton direction
+        let mut q: Vec<f32> = grad.iter().map(|g| -g).collect();
+        let mut alphas = Vec::with_capacity(history.len());
+        for (s, y, rho) in history.iter().rev() {
+            let alpha = rho * dot(s, &q);
+            for i in 0..n {
+                q[i] -= alpha * y[i];
+            }
+            alphas.push(alpha);
+        }
+        if let Some((s, y, _)) = history.back() {
+            let gamma = dot(s, y) / dot(y, y).max(f32::EPSILON);
+            q.iter_mut().for_each(|q| *q *= gamma);
+        } else {
+            // first step moves the steepest parameter by a tenth of its range
+            let scale = (0..n)
+                .filter(|i| bounds[*i].1 > bounds[*i].0)
+                .map(|i| (q[i] / (bounds[i].1 - bounds[i].0)).abs())
+                .fold(0.0, f32::max);
+            if scale > 0.0 {
+                q.iter_mut().for_each(|q| *q *= 0.1 / scale);
+            }
+        }
+        for ((s, y, rho), alpha) in history.iter().zip(alphas.into_iter().rev()) {
+            let beta = rho * dot(y, &q);
+            for i in 0..n {
+                q[i] += s[i] * (alpha - beta);
+            }
+        }
+        let mut direction = q;
+
+        // don't push against active bounds
+        for i in 0..n {
+            let (low, high) = bounds[i];
+            if (x[i] <= low && direction[i] < 0.0) || (x[i] >= high && direction[i] > 0.0) {
+                direction[i] = 0.0;
+            }
+        }
+        if dot(&direction, &grad) >= 0.0 {
+            history.clear();
+            direction = grad.iter().map(|g| -g).collect();
+        }
+
+        let mut t = 1.0;
+        let mut next = None;
+        for _ in 0..12 {
+            let mut candidate: Vec<f32> = (0..n).map(|i| x[i] + t * direction[i]).collect();
+            project(&mut candidate, bounds);
+            let f = objective.value(&candidate);
+            let moved: Vec<f32> = (0..n).map(|i| candidate[i] - x[i]).collect();
+            if f <= fx + 1e-4 * dot(&grad, &moved) {
+                next = Some((candidate, f));
+                break;
+            }
+            t *= 0.5;
+        }
+        let Some((next_x, next_f)) = next else {
+            break;
+        };
+
+        let next_grad = objective.gradient(&next_x, next_f, bounds, config.step);
+        let s: Vec<f32> = (0..n).map(|i| next_x[i] - x[i]).collect();
+        let y: Vec<f32> = (0..n).map(|i| next_grad[i] - grad[i]).collect();
+        let sy = dot(&s, &y);
+        if sy > 1e-10 {
+            history.push_back((s, y, 1.0 / sy));
+            if history.len() > config.memory {
+                history.pop_front();
+            }
+        }
+
+        let improvement = (fx - next_f) / fx.abs().max(f32::EPSILON);
+        x = next_x;
+        fx = next_f;
+        grad = next_grad;
+        if improvement < config.tolerance {
+            break;
+        }
+    }
+
+    (x, fx)
+}
+
+/// Continuous refinement of every parameter of a program against `scorer`
+pub fn polish(
+    kinds: &[u8],
+    params: &[f32],
+    scorer: &dyn Scorer,
+    bounds: &ParamBounds,
+    config: &OptimizeConfig,
+) -> (Vec<f32>, f32) {
+    let bounds = bounds.for_program(kinds, params);
+    let mut objective = ProgramObjective::new(kinds, scorer);
+    objective.renderer = config.renderer;
+    minimize(&mut objective, params, &bounds, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(x - 3)² + 10 (y + 1)²`
+    struct Bowl;
+
+    impl Objective for Bowl {
+        fn value(&mut self, x: &[f32]) -> f32 {
+            (x[0] - 3.0).powi(2) + 10.0 * (x[1] + 1.0).powi(2)
+        }
+    }
+
+    const BOUNDS: [(f32, f32); 2] = [(0.0, 10.0), (-5.0, 5.0)];
+
+    fn config(optimizer: Optimizer) -> OptimizeConfig {
+        OptimizeConfig {
+            optimizer,
+            iterations: 300,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lbfgs_finds_the_minimum() {
+        let (x, fx) = minimize(&mut Bowl, &[8.0, 4.0], &BOUNDS, &config(Optimizer::Lbfgs));
+        assert!(
+            (x[0] - 3.0).abs() < 1e-2 && (x[1] + 1.0).abs() < 1e-2,
+            "{x:?}"
+        );
+        assert!(fx < 1e-3);
+    }
+
+    #[test]
+    fn adam_approaches_the_minimum() {
+        let start = Bowl.value(&[8.0, 4.0]);
+        let (x, fx) = minimize(&mut Bowl, &[8.0, 4.0], &BOUNDS, &config(Optimizer::Adam));
+        assert!(fx < start * 0.01, "{fx}");
+        assert!(
+            (x[0] - 3.0).abs() < 0.5 && (x[1] + 1.0).abs() < 0.5,
+            "{x:?}"
+        );
+    }
+
+    #[test]
+    fn minima_outside_the_box_end_on_its_boundary() {
+        let bounds = [(4.0, 10.0), (0.0, 5.0)];
+        for optimizer in [Optimizer::Adam, Optimizer::Lbfgs] {
+            let (x, _) = minimize(&mut Bowl, &[8.0, 4.0], &bounds, &config(optimizer));
+            assert!(
+                (x[0] - 4.0).abs() < 0.2 && x[1].abs() < 0.2,
+                "{optimizer:?} {x:?}"
+            );
+            assert!(x
+                .iter()
+                .zip(&bounds)
+                .all(|(x, (low, high))| (low..=high).contains(&x)));
+        }
+    }
+
+    #[test]
+    fn bounds_pin_what_a_kind_ignores() {
+        let mut params = [2.0; 10];
+        params[9] = 0.0;
+        let bounds = ParamBounds::default().for_program(&[1], &params);
+        assert_eq!(bounds[0], (1.0, 20.0));
+        // a sphere has one size
+        assert_eq!(bounds[1], (2.0, 2.0));
+        assert_eq!(bounds[3], (0.0, 5.0));
+        assert_eq!(bounds[9], (0.0, 0.0));
+    }
+}
@@ -0,0 +1,518 @@
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use reedline::{
+    default_emacs_keybindings, ColumnarMenu, Completer, Emacs, FileBackedHistory, KeyCode,
+    KeyModifiers, MenuBuilder, Reedline, ReedlineEvent, ReedlineMenu, Span, Suggestion,
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    grid::{self, Axis, Refine},
+    sample::{SampleConfig, Sampling},
+};
+
+/// Entries kept in the history file
+pub const HISTORY_SIZE: usize = 1000;
+
+/// Primitive names accepted by `kind`, indexed by kind
+pub const KINDS: [&str; 3] = ["cube", "sphere", "cylinder"];
+
+/// Samplers accepted by `sample`, as [`Sampling`] names them
+pub const SAMPLERS: [&str; 5] = ["grid", "sobol", "halton", "latin", "random"];
+
+/// Names of the ten parameters of a primitive, for `set`
+pub const PARAMS: [&str; 10] = ["sx", "sy", "sz", "tx", "ty", "tz", "rx", "ry", "rz", "op"];
+
+/// One line typed at the prompt
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// restrict the search to one primitive kind, any kind for `None`
+    Kind(Option<u8>),
+    Size([Axis; 3]),
+    Translation([Axis; 3]),
+    Rotation([Axis; 3]),
+    /// sampler and budget of the next searches
+    Sample(SampleConfig),
+    /// coarse-to-fine settings of the next searches
+    Refine(Refine),
+    /// put candidate N of the last search's ranking where it was searched for
+    Commit(usize),
+    /// print the last search's ranking
+    Top,
+    /// back to any kind and the session's initial ranges
+    Reset,
+    /// search again, for primitive N with the others fixed or for a new one
+    Search(Option<usize>),
+    List,
+    /// print the program as µcad
+    Ucad,
+    /// revert the last change to the program
+    Undo,
+    Delete(usize),
+    /// primitive, and rank of the candidate of the last search put in its place
+    Replace(usize, usize),
+    /// primitive, parameter index into [`PARAMS`] and value
+    Set(usize, usize, f32),
+    /// write the session to a file
+    Save(PathBuf),
+    /// continue a saved session
+    Load(PathBuf),
+    Help(Option<String>),
+    Quit,
+}
+
+struct Spec {
+    name: &'static str,
+    aliases: &'static [&'static str],
+    usage: &'static str,
+    about: &'static str,
+}
+
+const COMMANDS: &[Spec] = &[
+    Spec {
+        name: "kind",
+        aliases: &["k"],
+        usage: "kind <cube|sphere|cylinder|any>",
+        about: "search only one primitive kind, or 0, 1, 2",
+    },
+    Spec {
+        name: "size",
+        aliases: &["s"],
+        usage: "size <low..high[:steps]> [y] [z]",
+        about: "sizes searched, for all axes or each",
+    },
+    Spec {
+        name: "translation",
+        aliases: &["t"],
+        usage: "translation <low..high[:steps]> [y] [z]",
+        about: "translations searched, for all axes or each",
+    },
+    Spec {
+        name: "rotation",
+        aliases: &["r"],
+        usage: "rotation <low..high[:steps]> [y] [z]",
+        about: "rotations searched in degrees, for all axes or each",
+    },
+    Spec {
+        name: "sample",
+        aliases: &["m"],
+        usage: "sample <grid|sobol|halton|latin|random> [budget]",
+        about: "draw budget candidates per kind and refined cell instead of the grid",
+    },
+    Spec {
+        name: "refine",
+        aliases: &["f"],
+        usage: "refine <levels|off> [cells] [tolerance]",
+        about: "re-grid around the best cells until the score stalls",
+    },
+    Spec {
+        name: "commit",
+        aliases: &["c"],
+        usage: "commit [rank]",
+        about: "add a ranked candidate, the best by default, or replace the primitive it was searched for",
+    },
+    Spec {
+        name: "top",
+        aliases: &["ranking"],
+        usage: "top",
+        about: "list the best distinct candidates of the last search, to commit by rank",
+    },
+    Spec {
+        name: "reset",
+        aliases: &["a"],
+        usage: "reset",
+        about: "search any kind again, in the ranges the session started with",
+    },
+    Spec {
+        name: "search",
+        aliases: &[""],
+        usage: "search [index]",
+        about: "search a new primitive, or primitive N with the others fixed",
+    },
+    Spec {
+        name: "list",
+        aliases: &["l", "ls"],
+        usage: "list",
+        about: "print the program with the score up to and without each primitive",
+    },
+    Spec {
+        name: "ucad",
+        aliases: &["u"],
+        usage: "ucad",
+        about: "print the program as µcad",
+    },
+    Spec {
+        name: "undo",
+        aliases: &["z"],
+        usage: "undo",
+        about: "revert the last commit, replace, delete or set",
+    },
+    Spec {
+        name: "delete",
+        aliases: &["d", "rm"],
+        usage: "delete <index>",
+        about: "remove primitive N",
+    },
+    Spec {
+        name: "replace",
+        aliases: &[],
+        usage: "replace <index> [rank]",
+        about: "put a ranked candidate, the best by default, in place of primitive N",
+    },
+    Spec {
+        name: "set",
+        aliases: &[],
+        usage: "set <index> <sx|sy|sz|tx|ty|tz|rx|ry|rz|op> <value>",
+        about: "change one parameter, op takes | or &",
+    },
+    Spec {
+        name: "save",
+        aliases: &[],
+        usage: "save <file>",
+        about: "write target, program, ranges and seed to a JSON file",
+    },
+    Spec {
+        name: "load",
+        aliases: &[],
+        usage: "load <file>",
+        about: "continue a saved session",
+    },
+    Spec {
+        name: "help",
+        aliases: &["h", "?"],
+        usage: "help [command]",
+        about: "list commands or describe one",
+    },
+    Spec {
+        name: "quit",
+        aliases: &["q", "exit"],
+        usage: "quit",
+        about: "leave the session",
+    },
+];
+
+fn lookup(word: &str) -> Option<&'static Spec> {
+    COMMANDS
+        .iter()
+        .find(|spec| spec.name == word || spec.aliases.contains(&word))
+}
+
+/// Command and arguments of a line, also splitting the short forms `k0` and
+/// `s1..20` that were typed without a space
+fn split(line: &str) -> (&str, Vec<&str>) {
+    let mut words = line.split_whitespace();
+    let first = words.next().unwrap_or_default();
+    let mut args: Vec<&str> = words.collect();
+    if lookup(first).is_some() || !first.is_char_boundary(1) {
+        return (first, args);
+    }
+    let (head, tail) = first.split_at(1);
+    if lookup(head).is_some()
+        && tail.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.')
+    {
+        args.insert(0, tail);
+        return (head, args);
+    }
+    (first, args)
+}
+
+pub fn parse(line: &str) -> anyhow::Result<Command> {
+    let (word, args) = split(line);
+    let Some(spec) = lookup(word) else {
+        Err(anyhow!("unknown command `{word}`, try `help`"))?
+    };
+    let usage = || anyhow!("usage: {}", spec.usage);
+    let range = |args| grid::axes(args).map_err(|e| anyhow!("{e}, usage: {}", spec.usage));
+    let command = match (spec.name, args.as_slice()) {
+        ("kind", [kind]) => Command::Kind(parse_kind(kind)?),
+        ("size", args) => Command::Size(range(args)?),
+        ("translation", args) => Command::Translation(range(args)?),
+        ("rotation", args) => Command::Rotation(range(args)?),
+        ("sample", [sampling, rest @ ..]) if rest.len() <= 1 => {
+            let sampling = Sampling::from_str(sampling, true)
+                .map_err(|_| anyhow!("unknown sampler `{sampling}`, usage: {}", spec.usage))?;
+            let budget = match rest.first() {
+                Some(budget) => budget
+                    .parse()
+                    .map_err(|_| anyhow!("invalid budget `{budget}`, usage: {}", spec.usage))?,
+                None => 0,
+            };
+            Command::Sample(SampleConfig { sampling, budget })
+        }
+        ("refine", ["off"]) => Command::Refine(Refine::default()),
+        ("refine", [levels, rest @ ..]) if rest.len() <= 2 => {
+            let number = |word: &str| anyhow!("invalid number `{word}`, usage: {}", spec.usage);
+            let mut refine = Refine {
+                levels: levels.parse().map_err(|_| number(levels))?,
+                ..Default::default()
+            };
+            if let Some(keep) = rest.first() {
+                refine.keep = keep.parse().map_err(|_| number(keep))?;
+            }
+            if let Some(tolerance) = rest.get(1) {
+                refine.tolerance = tolerance.parse().map_err(|_| number(tolerance))?;
+            }
+            Command::Refine(refine)
+        }
+        ("commit", []) => Command::Commit(0),
+        ("commit", [rank]) => Command::Commit(parse_rank(rank)?),
+        ("top", []) => Command::Top,
+        ("reset", []) => Command::Reset,
+        ("search", []) => Command::Search(None),
+        ("search", [index]) => Command::Search(Some(parse_index(index)?)),
+        ("list", []) => Command::List,
+        ("ucad", []) => Command::Ucad,
+        ("undo", []) => Command::Undo,
+        ("delete", [index]) => Command::Delete(parse_index(index)?),
+        ("replace", [index]) => Command::Replace(parse_index(index)?, 0),
+        ("replace", [index, rank]) => Command::Replace(parse_index(index)?, parse_rank(rank)?),
+        ("set", [index, param, value]) => {
+            let param = PARAMS
+                .iter()
+                .position(|name| name == param)
+                .or_else(|| param.parse().ok().filter(|i| *i < PARAMS.len()))
+                .ok_or_else(|| anyhow!("unknown parameter `{param}`, usage: {}", spec.usage))?;
+            let value = match *value {
+                "|" => 0.0,
+                "&" => 2.0,
+                value => value
+                    .parse()
+                    .map_err(|_| anyhow!("invalid value `{value}`"))?,
+            };
+            Command::Set(parse_index(index)?, param, value)
+        }
+        ("save", [path]) => Command::Save(path.into()),
+        ("load", [path]) => Command::Load(path.into()),
+        ("help", []) => Command::Help(None),
+        ("help", [topic]) => Command::Help(Some(topic.to_string())),
+        ("quit", []) => Command::Quit,
+        _ => Err(usage())?,
+    };
+    Ok(command)
+}
+
+fn parse_kind(word: &str) -> anyhow::Result<Option<u8>> {
+    if word == "any" {
+        return Ok(None);
+    }
+    let kind = match word.parse::<u8>() {
+        Ok(kind) => kind as usize,
+        Err(_) => KINDS
+            .iter()
+            .position(|name| *name == word)
+            .unwrap_or(KINDS.len()),
+    };
+    if kind >= KINDS.len() {
+        Err(anyhow!(
+            "unknown kind `{word}`, expected {} or any",
+            KINDS.join(", ")
+        ))?
+    }
+    Ok(Some(kind as u8))
+}
+
+fn parse_index(word: &str) -> anyhow::Result<usize> {
+    word.parse()
+        .map_err(|_| anyhow!("expected a primitive index, got `{word}`"))
+}
+
+fn parse_rank(word: &str) -> anyhow::Result<usize> {
+    word.parse()
+        .map_err(|_| anyhow!("expected a candidate rank, got `{word}`"))
+}
+
+/// Command list, or the usage of a single command
+pub fn help(topic: Option<&str>) -> anyhow::Result<String> {
+    match topic {
+        Some(topic) => {
+            let spec = lookup(topic).ok_or_else(|| anyhow!("unknown command `{topic}`"))?;
+            let aliases: Vec<_> = spec.aliases.iter().filter(|a| !a.is_empty()).collect();
+            let aliases = match aliases.is_empty() {
+                true => String::new(),
+                false => format!(" (also {})", itertools::join(aliases, ", ")),
+            };
+            Ok(format!("{}{aliases}\n  {}", spec.usage, spec.about))
+        }
+        None => Ok(COMMANDS
+            .iter()
+            .map(|spec| format!("{:<32}{}", spec.usage, spec.about))
+            .collect::<Vec<_>>()
+            .join("\n")),
+    }
+}
+
+/// Tab completion of command names, kinds and help topics
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CommandCompleter;
+
+impl Completer for CommandCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..];
+        let previous: Vec<&str> = line[..start].split_whitespace().collect();
+
+        let commands = || COMMANDS.iter().map(|spec| (spec.name, Some(spec.about)));
+        let options: Vec<(&str, Option<&str>)> = match previous.as_slice() {
+            [] => commands().collect(),
+            [command] => match lookup(command).map(|spec| spec.name) {
+                Some("kind") => KINDS.iter().chain(&["any"]).map(|k| (*k, None)).collect(),
+                Some("sample") => SAMPLERS.iter().map(|s| (*s, None)).collect(),
+                Some("help") => commands().collect(),
+                _ => vec![],
+            },
+            [command, _] if lookup(command).is_some_and(|spec| spec.name == "set") => {
+                PARAMS.iter().map(|p| (*p, None)).collect()
+            }
+            _ => vec![],
+        };
+        options
+            .into_iter()
+            .filter(|(value, _)| value.starts_with(word))
+            .map(|(value, description)| Suggestion {
+                value: value.to_string(),
+                description: description.map(str::to_string),
+                span: Span::new(start, pos),
+                append_whitespace: true,
+                ..Default::default()
+            })
+            .collect()
+    }
+}
+
+/// Where the prompt history survives between sessions
+pub fn history_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("paramesh").join("history"))
+}
+
+/// Where the session is saved on exit, for `--resume`
+pub fn autosave_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("paramesh").join("session.json"))
+}
+
+/// Emacs bindings with tab completion, history in [`history_path`] when
+/// there is a data directory
+pub fn line_editor() -> anyhow::Result<Reedline> {
+    let mut keybindings = default_emacs_keybindings();
+    keybindings.add_binding(
+        KeyModifiers::NONE,
+        KeyCode::Tab,
+        ReedlineEvent::UntilFound(vec![
+            ReedlineEvent::Menu("completion_menu".into()),
+            ReedlineEvent::MenuNext,
+        ]),
+    );
+    let menu = ColumnarMenu::default().with_name("completion_menu");
+    let mut editor = Reedline::create()
+        .with_completer(Box::new(CommandCompleter))
+        .with_menu(ReedlineMenu::EngineCompleter(Box::new(menu)))
+        .with_edit_mode(Box::new(Emacs::new(keybindings)));
+    if let Some(path) = history_path() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        editor = editor.with_history(Box::new(FileBackedHistory::with_file(HISTORY_SIZE, path)?));
+    }
+    Ok(editor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_forms_split_off_their_argument() {
+        assert_eq!(split("k0"), ("k", vec!["0"]));
+        assert_eq!(split("s1..20 2 3"), ("s", vec!["1..20", "2", "3"]));
+        assert_eq!(split("t-1..1"), ("t", vec!["-1..1"]));
+        assert_eq!(split("  list  "), ("list", vec![]));
+        assert_eq!(split("kind cube"), ("kind", vec!["cube"]));
+        // whole words are never split, unknown ones are left to fail
+        assert_eq!(split("ls"), ("ls", vec![]));
+        assert_eq!(split("x1"), ("x1", vec![]));
+        assert_eq!(split("ñ1"), ("ñ1", vec![]));
+        assert_eq!(split(""), ("", vec![]));
+    }
+
+    #[test]
+    fn kinds_by_name_index_or_any() {
+        assert_eq!(parse("kind sphere").unwrap(), Command::Kind(Some(1)));
+        assert_eq!(parse("k2").unwrap(), Command::Kind(Some(2)));
+        assert_eq!(parse("k any").unwrap(), Command::Kind(None));
+        assert!(parse("kind 3").is_err());
+        assert!(parse("kind cone").is_err());
+        assert!(parse("kind").is_err());
+    }
+
+    #[test]
+    fn ranges_for_all_axes_or_each() {
+        let Command::Size(all) = parse("s1..20:4").unwrap() else {
+            panic!("not a size");
+        };
+        assert_eq!(all, [Axis::new(1.0, 20.0, 4); 3]);
+        let Command::Rotation(each) = parse("rotation 0 0..90:2 45").unwrap() else {
+            panic!("not a rotation");
+        };
+        assert_eq!(each[0], Axis::new(0.0, 0.0, 1));
+        assert_eq!(each[1], Axis::new(0.0, 90.0, 2));
+        assert!(parse("t 1 2").is_err());
+        assert!(parse("t 2..1").is_err());
+    }
+
+    #[test]
+    fn empty_lines_search_and_aliases_resolve() {
+        assert_eq!(parse("").unwrap(), Command::Search(None));
+        assert_eq!(parse("search 2").unwrap(), Command::Search(Some(2)));
+        assert_eq!(parse("q").unwrap(), Command::Quit);
+        assert_eq!(parse("exit").unwrap(), Command::Quit);
+        assert_eq!(parse("? kind").unwrap(), Command::Help(Some("kind".into())));
+        assert_eq!(
+            parse("save out.json").unwrap(),
+            Command::Save("out.json".into())
+        );
+    }
+
+    #[test]
+    fn edits_take_indices_and_parameters() {
+        assert_eq!(parse("rm 1").unwrap(), Command::Delete(1));
+        assert_eq!(parse("replace 1").unwrap(), Command::Replace(1, 0));
+        assert_eq!(parse("set 0 ty 2.5").unwrap(), Command::Set(0, 4, 2.5));
+        assert_eq!(parse("set 1 9 &").unwrap(), Command::Set(1, 9, 2.0));
+        assert_eq!(parse("set 1 op |").unwrap(), Command::Set(1, 9, 0.0));
+        assert!(parse("set 0 10 1").is_err());
+        assert!(parse("set 0 tx x").is_err());
+        assert!(parse("delete -1").is_err());
+    }
+
+    #[test]
+    fn unknown_commands_and_arities_fail() {
+        let unknown = parse("frobnicate").unwrap_err().to_string();
+        assert!(unknown.contains("unknown command"), "{unknown}");
+        let usage = parse("undo 1").unwrap_err().to_string();
+        assert_eq!(usage, "usage: undo");
+    }
+
+    #[test]
+    fn help_names_aliases() {
+        assert!(help(Some("l")).unwrap().contains("(also l, ls)"));
+        assert!(help(Some("nothing")).is_err());
+        assert_eq!(help(None).unwrap().lines().count(), COMMANDS.len());
+    }
+
+    #[test]
+    fn completion_offers_matching_words() {
+        let values = |line: &str| -> Vec<String> {
+            CommandCompleter
+                .complete(line, line.len())
+                .into_iter()
+                .map(|s| s.value)
+                .collect()
+        };
+        assert_eq!(values("kind c"), vec!["cube", "cylinder"]);
+        assert_eq!(values("set 0 r"), vec!["rx", "ry", "rz"]);
+        assert!(values("li").contains(&"list".to_string()));
+        assert!(values("delete 0 ").is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::grid::Grid;
+
+/// Searched parameters of a primitive, sx to rz
+const DIMS: usize = 9;
+
+/// Draws candidate parameters from a grid's ranges
+pub trait Sampler: Send + Sync {
+    /// Parameters of `budget` candidates, with the union operation
+    fn sample(&self, grid: &Grid, budget: usize) -> Vec<[f32; 10]>;
+}
+
+/// Every point of the grid, whatever the budget
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lattice;
+
+impl Sampler for Lattice {
+    fn sample(&self, grid: &Grid, _budget: usize) -> Vec<[f32; 10]> {
+        grid.candidates()
+    }
+}
+
+/// Radical inverses in the first nine prime bases
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Halton;
+
+impl Halton {
+    const BASES: [u32; DIMS] = [2, 3, 5, 7, 11, 13, 17, 19, 23];
+
+    fn radical_inverse(mut i: u32, base: u32) -> f32 {
+        let mut inverse = 0.0;
+        let mut scale = 1.0 / base as f64;
+        while i > 0 {
+            inverse += (i % base) as f64 * scale;
+            i /= base;
+            scale /= base as f64;
+        }
+        inverse as f32
+    }
+}
+
+impl Sampler for Halton {
+    fn sample(&self, grid: &Grid, budget: usize) -> Vec<[f32; 10]> {
+        // the first point is the origin, a corner of every range
+        (1..=budget as u32)
+            .map(|i| grid.point(Self::BASES.map(|base| Self::radical_inverse(i, base))))
+            .collect()
+    }
+}
+
+/// Gray code Sobol points with Joe and Kuo's direction numbers
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sobol;
+
+impl Sobol {
+    const BITS: usize = 32;
+
+    /// Degree, coefficients and initial numbers of the primitive polynomial of
+    /// every dimension after the first
+    const POLYNOMIALS: [(usize, u32, &'static [u32]); DIMS - 1] = [
+        (1, 0, &[1]),
+        (2, 1, &[1, 3]),
+        (3, 1, &[1, 3, 1]),
+        (3, 2, &[1, 1, 1]),
+        (4, 1, &[1, 1, 3, 3]),
+        (4, 4, &[1, 3, 5, 13]),
+        (5, 2, &[1, 1, 5, 5, 17]),
+        (5, 4, &[1, 1, 5, 5, 5]),
+    ];
+
+    fn directions() -> [[u32; Self::BITS]; DIMS] {
+        let mut directions = [[0; Self::BITS]; DIMS];
+        for (i, v) in directions[0].iter_mut().enumerate() {
+            *v = 1 << (Self::BITS - 1 - i);
+        }
+        for (v, &(degree, a, m)) in directions[1..].iter_mut().zip(&Self::POLYNOMIALS) {
+            for i in 0..Self::BITS {
+                v[i] = match i < degree {
+                    true => m[i] << (Self::BITS - 1 - i),
+                    false => {
+                        let mut next = v[i - degree] ^ (v[i - degree] >> degree);
+                        for k in 1..degree {
+                            next ^= ((a >> (degree - 1 - k)) & 1) * v[i - k];
+                        }
+                        next
+                    }
+                };
+            }
+        }
+        directions
+    }
+}
+
+impl Sampler for Sobol {
+    fn sample(&self, grid: &Grid, budget: usize) -> Vec<[f32; 10]> {
+        let directions = Self::directions();
+        let mut x = [0u32; DIMS];
+        // skips the origin like Halton
+        (0..budget as u32)
+            .map(|i| {
+                let bit = i.trailing_ones() as usize;
+                for (x, v) in x.iter_mut().zip(&directions) {
+                    *x ^= v[bit];
+                }
+                grid.point(x.map(|x| (x as f64 / (1u64 << Self::BITS) as f64) as f32))
+            })
+            .collect()
+    }
+}
+
+/// One point in every row and column of a `budget` sided lattice, jittered
+/// within its cell
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LatinHypercube {
+    pub seed: u64,
+}
+
+impl Sampler for LatinHypercube {
+    fn sample(&self, grid: &Grid, budget: usize) -> Vec<[f32; 10]> {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(self.seed);
+        let strata: [Vec<usize>; DIMS] = std::array::from_fn(|_| {
+            let mut rows: Vec<usize> = (0..budget).collect();
+            rows.shuffle(&mut rng);
+            rows
+        });
+        (0..budget)
+            .map(|i| {
+                grid.point(std::array::from_fn(|d| {
+                    (strata[d][i] as f32 + rng.random::<f32>()) / budget as f32
+                }))
+            })
+            .collect()
+    }
+}
+
+/// Independent uniform points
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Uniform {
+    pub seed: u64,
+}
+
+impl Sampler for Uniform {
+    fn sample(&self, grid: &Grid, budget: usize) -> Vec<[f32; 10]> {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(self.seed);
+        (0..budget)
+            .map(|_| grid.point(std::array::from_fn(|_| rng.random())))
+            .collect()
+    }
+}
+
+/// How candidates are drawn from the search ranges
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum Sampling {
+    /// every combination of the ranges' steps
+    #[default]
+    Grid,
+    Sobol,
+    Halton,
+    /// Latin hypercube
+    Latin,
+    Random,
+}
+
+impl Sampling {
+    /// The sampler, with `seed` for the random ones
+    pub fn sampler(&self, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            Sampling::Grid => Box::new(Lattice),
+            Sampling::Sobol => Box::new(Sobol),
+            Sampling::Halton => Box::new(Halton),
+            Sampling::Latin => Box::new(LatinHypercube { seed }),
+            Sampling::Random => Box::new(Uniform { seed }),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .map_or_else(String::new, |value| value.get_name().to_string())
+    }
+}
+
+/// Sampler and evaluation budget of a search
+#[derive(clap::Args, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SampleConfig {
+    /// how candidates are drawn from the search ranges
+    #[arg(long = "sampler", value_enum, default_value_t)]
+    pub sampling: Sampling,
+    /// candidates drawn for each kind and each refined cell by the samplers
+    /// other than the grid, which takes all its points; as many as the grid
+    /// has when 0
+    #[arg(long, default_value_t = 0)]
+    pub budget: usize,
+}
+
+impl SampleConfig {
+    pub fn budget(&self, grid: &Grid) -> usize {
+        match (self.sampling, self.budget) {
+            (Sampling::Grid, _) | (_, 0) => grid.len(),
+            (_, budget) => budget,
+        }
+    }
+
+    /// Parameters of the candidates searched in `grid`'s ranges, the same
+    /// for the same `seed`
+    pub fn candidates(&self, grid: &Grid, seed: u64) -> Vec<[f32; 10]> {
+        self.sampling.sampler(seed).sample(grid, self.budget(grid))
+    }
+}
+
+impl fmt::Display for SampleConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.sampling, self.budget) {
+            (Sampling::Grid, _) | (_, 0) => write!(f, "{}", self.sampling.name()),
+            (sampling, budget) => write!(f, "{} of {budget}", sampling.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Axis;
+
+    fn unit() -> Grid {
+        let axes = [Axis::new(0.0, 1.0, 2); 3];
+        Grid {
+            size: axes,
+            translation: axes,
+            rotation: axes,
+        }
+    }
+
+    /// Which of `n` equal slices of every dimension each point falls in
+    fn strata(points: &[[f32; 10]], n: usize) -> [Vec<usize>; DIMS] {
+        std::array::from_fn(|d| {
+            let mut rows: Vec<usize> = points.iter().map(|p| (p[d] * n as f32) as usize).collect();
+            rows.sort();
+            rows
+        })
+    }
+
+    #[test]
+    fn sobol_direction_numbers() {
+        let directions = Sobol::directions();
+        let m = |d: usize| -> Vec<u32> { (0..5).map(|i| directions[d][i] >> (31 - i)).collect() };
+        assert_eq!(m(0), [1; 5]);
+        // x + 1 and x^2 + x + 1
+        assert_eq!(m(1), [1, 3, 5, 15, 17]);
+        assert_eq!(m(2), [1, 3, 3, 9, 29]);
+
+        let points = Sobol.sample(&unit(), 3);
+        assert_eq!(
+            points.iter().map(|p| p[0]).collect::<Vec<_>>(),
+            [0.5, 0.75, 0.25]
+        );
+        assert_eq!(
+            points.iter().map(|p| p[1]).collect::<Vec<_>>(),
+            [0.5, 0.25, 0.75]
+        );
+    }
+
+    #[test]
+    fn sobol_points_fill_every_slice() {
+        // with the skipped origin, the first 2^k points hit each of 2^k slices
+        let mut points = Sobol.sample(&unit(), 15);
+        points.push([0.0; 10]);
+        let all: Vec<usize> = (0..16).collect();
+        for rows in strata(&points, 16) {
+            assert_eq!(rows, all);
+        }
+    }
+
+    #[test]
+    fn latin_hypercube_hits_every_row_once() {
+        let points = LatinHypercube { seed: 7 }.sample(&unit(), 10);
+        let all: Vec<usize> = (0..10).collect();
+        for rows in strata(&points, 10) {
+            assert_eq!(rows, all);
+        }
+        assert_eq!(points, LatinHypercube { seed: 7 }.sample(&unit(), 10));
+        assert_ne!(points, LatinHypercube { seed: 8 }.sample(&unit(), 10));
+    }
+
+    #[test]
+    fn budget_and_seed() {
+        let random = SampleConfig {
+            sampling: Sampling::Random,
+            budget: 5,
+        };
+        assert_eq!(random.candidates(&unit(), 1).len(), 5);
+        assert_eq!(random.candidates(&unit(), 1), random.candidates(&unit(), 1));
+        assert_ne!(random.candidates(&unit(), 1), random.candidates(&unit(), 2));
+        // the grid takes all its points whatever the budget
+        let grid = SampleConfig {
+            sampling: Sampling::Grid,
+            budget: 5,
+        };
+        assert_eq!(grid.candidates(&unit(), 1).len(), 512);
+        assert_eq!(grid.to_string(), "grid");
+        assert_eq!(random.to_string(), "random of 5");
+    }
+}
@@ -0,0 +1,127 @@
+use rand::prelude::*;
+use rerun::external::glam::Vec3;
+
+use crate::{
+    bvh::{Aabb, Bvh},
+    cloud::PointCloud,
+    mesh::Mesh,
+};
+
+/// Virtual depth cameras around a mesh, for targets that look like real scans
+#[derive(clap::Args, Clone, Copy, Debug, PartialEq)]
+pub struct Scanner {
+    /// viewpoints spread evenly around the target
+    #[arg(long = "scan-cameras", default_value_t = 3)]
+    pub cameras: usize,
+    /// camera distance from the centre, in bounding radii
+    #[arg(long = "scan-distance", default_value_t = 3.0)]
+    pub distance: f32,
+    /// rays per side of each camera's square image
+    #[arg(long = "scan-resolution", default_value_t = 64)]
+    pub resolution: usize,
+    /// standard deviation of the depth error, in target units
+    #[arg(long = "scan-noise", default_value_t = 0.0)]
+    pub noise: f32,
+    /// probability of losing each return
+    #[arg(long = "scan-dropout", default_value_t = 0.0)]
+    pub dropout: f32,
+    /// stray points added, as a fraction of the returns
+    #[arg(
+        id = "scan_outliers",
+        long = "scan-outliers",
+        value_name = "OUTLIERS",
+        default_value_t = 0.0
+    )]
+    pub outliers: f32,
+    /// seed of the noise, dropout and outliers, equal seeds give equal scans
+    #[arg(
+        id = "scan_seed",
+        long = "scan-seed",
+        value_name = "SEED",
+        default_value_t = 0
+    )]
+    pub seed: u64,
+}
+
+impl Default for Scanner {
+    fn default() -> Self {
+        Self {
+            cameras: 3,
+            distance: 3.0,
+            resolution: 64,
+            noise: 0.0,
+            dropout: 0.0,
+            outliers: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl Scanner {
+    /// Camera positions on a Fibonacci sphere around the mesh's bounding box
+    pub fn viewpoints(&self, mesh: &Mesh) -> Vec<Vec3> {
+        let aabb = Aabb::from_points(&mesh.positions);
+        let radius = (aabb.size().length() * 0.5).max(f32::EPSILON);
+        let golden = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+        (0..self.cameras)
+            .map(|i| {
+                let z = 1.0 - 2.0 * (i as f32 + 0.5) / self.cameras as f32;
+                let r = (1.0 - z * z).sqrt();
+                let phi = golden * i as f32;
+                let dir = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                aabb.center() + dir * radius * self.distance
+            })
+            .collect()
+    }
+
+    /// The first surface every ray hits, so far sides and hidden cavities are
+    /// missing, then noise, dropout and outliers on top
+    pub fn scan(&self, mesh: &Mesh) -> PointCloud {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(self.seed);
+        let bvh = Bvh::new(mesh);
+        let aabb = Aabb::from_points(&mesh.positions);
+        let radius = (aabb.size().length() * 0.5).max(f32::EPSILON);
+        let n = self.resolution.max(1);
+
+        let mut cloud = PointCloud::default();
+        for eye in self.viewpoints(mesh) {
+            let forward = (aabb.center() - eye).normalize();
+            let (right, up) = forward.any_orthonormal_pair();
+            // just wide enough to see the whole bounding sphere
+            let half = (radius / eye.distance(aabb.center()))
+                .min(0.99)
+                .asin()
+                .tan();
+            for (i, j) in (0..n).flat_map(|i| (0..n).map(move |j| (i, j))) {
+                let u = ((i as f32 + 0.5) / n as f32 * 2.0 - 1.0) * half;
+                let v = ((j as f32 + 0.5) / n as f32 * 2.0 - 1.0) * half;
+                let dir = (forward + right * u + up * v).normalize();
+                let Some(t) = bvh.raycast(eye, dir) else {
+                    continue;
+                };
+                if rng.random::<f32>() < self.dropout {
+                    continue;
+                }
+                let depth = t + self.noise * gaussian(&mut rng);
+                cloud.positions.push(eye + dir * depth);
+            }
+        }
+
+        let outliers = (cloud.len() as f32 * self.outliers).round() as usize;
+        let (low, high) = (aabb.min - aabb.size() * 0.1, aabb.max + aabb.size() * 0.1);
+        for _ in 0..outliers {
+            let p = Vec3::from_array(std::array::from_fn(|axis| {
+                rng.random_range(low[axis]..=high[axis])
+            }));
+            cloud.positions.push(p);
+        }
+        cloud
+    }
+}
+
+/// Standard normal sample by Box-Muller
+fn gaussian(rng: &mut impl Rng) -> f32 {
+    let u = 1.0 - rng.random::<f32>();
+    let v = rng.random::<f32>();
+    (-2.0 * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+}
@@ -0,0 +1,584 @@
+use std::{cell::OnceCell, str::FromStr};
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use rerun::external::glam::Vec3;
+
+use crate::{
+    bvh::Bvh,
+    mesh::{Mesh, Renderer},
+    metric::{
+        nearest, nearest_distances, robust_chamfer, Loss, Metric, Reduce, Robust, SDF_SAMPLES,
+        SURFACE_SAMPLES,
+    },
+    sdf::ProgramSdf,
+    transform::Rigid,
+    volume::{sampled_iou, voxel_iou, IOU_SAMPLES, VOXEL_RESOLUTION},
+};
+
+/// A program to be scored, rendered on first use of [`Candidate::mesh`]
+pub struct Candidate<'a> {
+    pub kinds: &'a [u8],
+    pub params: &'a [f32],
+    mesh: OnceCell<Mesh>,
+    sdf: OnceCell<ProgramSdf>,
+    renderer: Renderer,
+    /// where the program has been moved to, an injected mesh already is
+    transform: Rigid,
+}
+
+impl<'a> Candidate<'a> {
+    pub fn new(kinds: &'a [u8], params: &'a [f32]) -> Self {
+        Self {
+            kinds,
+            params,
+            mesh: OnceCell::new(),
+            sdf: OnceCell::new(),
+            renderer: Renderer::default(),
+            transform: Rigid::IDENTITY,
+        }
+    }
+
+    pub fn with_mesh(kinds: &'a [u8], params: &'a [f32], mesh: Mesh) -> Self {
+        Self {
+            kinds,
+            params,
+            mesh: OnceCell::from(mesh),
+            sdf: OnceCell::new(),
+            renderer: Renderer::default(),
+            transform: Rigid::IDENTITY,
+        }
+    }
+
+    /// Renders with `renderer` instead of µcad when the mesh is first needed
+    pub fn with_renderer(mut self, renderer: Renderer) -> Self {
+        self.renderer = renderer;
+        self
+    }
+
+    /// The program moved by `transform`, which a mesh given to
+    /// [`Candidate::with_mesh`] must already be
+    pub fn with_transform(mut self, transform: Rigid) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn transform(&self) -> &Rigid {
+        &self.transform
+    }
+
+    pub fn mesh(&self) -> anyhow::Result<&Mesh> {
+        if let Some(mesh) = self.mesh.get() {
+            return Ok(mesh);
+        }
+        let mut mesh = self.renderer.render(self.kinds, self.params)?;
+        if !self.transform.is_identity() {
+            mesh = mesh.transformed(&self.transform);
+        }
+        Ok(self.mesh.get_or_init(|| mesh))
+    }
+
+    /// Analytic field of the program, built on first use without rendering;
+    /// in the program's own frame, ignoring [`Candidate::transform`]
+    pub fn sdf(&self) -> anyhow::Result<&ProgramSdf> {
+        if let Some(sdf) = self.sdf.get() {
+            return Ok(sdf);
+        }
+        let sdf = ProgramSdf::new(self.kinds, self.params)?;
+        Ok(self.sdf.get_or_init(|| sdf))
+    }
+
+    pub fn into_mesh(self) -> anyhow::Result<Mesh> {
+        self.mesh()?;
+        Ok(self.mesh.into_inner().unwrap_or_default())
+    }
+}
+
+/// Objective shared by the synthesizers, lower is better
+pub trait Scorer: Send + Sync {
+    /// Called once with the target before any candidate is scored
+    fn prepare(&mut self, target: &Mesh);
+
+    fn score(&self, candidate: &Candidate) -> anyhow::Result<f32>;
+
+    /// Gradient of the score with respect to the candidate's vertex positions
+    fn gradient(&self, _candidate: &Candidate) -> anyhow::Result<Option<Vec<Vec3>>> {
+        Ok(None)
+    }
+
+    /// Target points and how badly the candidate explains each of them
+    fn residuals(&self, _candidate: &Candidate) -> anyhow::Result<Option<Vec<(Vec3, f32)>>> {
+        Ok(None)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Chamfer {
+    target: Vec<Vec3>,
+    robust: Robust,
+}
+
+impl Chamfer {
+    pub fn new(robust: Robust) -> Self {
+        Self {
+            target: vec![],
+            robust,
+        }
+    }
+}
+
+impl Scorer for Chamfer {
+    fn prepare(&mut self, target: &Mesh) {
+        self.target = target.positions.clone();
+    }
+
+    fn score(&self, candidate: &Candidate) -> anyhow::Result<f32> {
+        Ok(robust_chamfer(
+            &self.target,
+            &candidate.mesh()?.positions,
+            &self.robust,
+        ))
+    }
+
+    fn gradient(&self, candidate: &Candidate) -> anyhow::Result<Option<Vec<Vec3>>> {
+        let points = &candidate.mesh()?.positions;
+        let mut grad = vec![Vec3::ZERO; points.len()];
+
+        let forward: Vec<_> = self.target.iter().map(|p| nearest(*p, points)).collect();
+        let d2: Vec<f32> = forward
+            .iter()
+            .map(|n| n.map_or(f32::INFINITY, |(_, d)| d))
+            .collect();
+        let weights = self.robust.pool_derivative(&d2);
+        for ((p, n), w) in self.target.iter().zip(forward).zip(weights) {
+            if let Some((j, _)) = n {
+                grad[j] += self.robust.forward * w * 2.0 * (points[j] - *p);
+            }
+        }
+
+        let backward: Vec<_> = points.iter().map(|q| nearest(*q, &self.target)).collect();
+        let d2: Vec<f32> = backward
+            .iter()
+            .map(|n| n.map_or(f32::INFINITY, |(_, d)| d))
+            .collect();
+        let weights = self.robust.pool_derivative(&d2);
+        for (j, (n, w)) in backward.into_iter().zip(weights).enumerate() {
+            if let Some((i, _)) = n {
+                grad[j] += self.robust.backward * w * 2.0 * (points[j] - self.target[i]);
+            }
+        }
+
+        Ok(Some(grad))
+    }
+
+    fn residuals(&self, candidate: &Candidate) -> anyhow::Result<Option<Vec<(Vec3, f32)>>> {
+        let d2 = nearest_distances(&self.target, &candidate.mesh()?.positions);
+        Ok(Some(self.target.iter().copied().zip(d2).collect()))
+    }
+}
+
+/// Chamfer-like score from the program's analytic field: target points are
+/// measured by their signed distance, the way back by level set samples. The
+/// field stays in the program's frame, target points are taken into it
+#[derive(Clone, Debug, Default)]
+pub struct Sdf {
+    target: Vec<Vec3>,
+    robust: Robust,
+}
+
+impl Sdf {
+    pub fn new(robust: Robust) -> Self {
+        Self {
+            target: vec![],
+            robust,
+        }
+    }
+}
+
+impl Scorer for Sdf {
+    fn prepare(&mut self, target: &Mesh) {
+        self.target = target.positions.clone();
+    }
+
+    fn score(&self, candidate: &Candidate) -> anyhow::Result<f32> {
+        let sdf = candidate.sdf()?;
+        if sdf.is_empty() {
+            return Ok(f32::INFINITY);
+        }
+        let inverse = candidate.transform().inverse();
+        let forward: Vec<f32> = self
+            .target
+            .iter()
+            .map(|p| sdf.distance(inverse.apply(*p)).powi(2))
+            .collect();
+        let mut score = self.robust.forward * self.robust.pool(&forward);
+        if self.robust.backward > 0.0 {
+            let samples = sdf.surface_samples(SDF_SAMPLES);
+            // Newton steps may all stall on odd intersections, sample the render then
+            let samples: Vec<Vec3> = match samples.is_empty() {
+                true => candidate.mesh()?.surface_samples(SDF_SAMPLES),
+                false => samples
+                    .iter()
+                    .map(|p| candidate.transform().apply(*p))
+                    .collect(),
+            };
+            let backward = nearest_distances(&samples, &self.target);
+            score += self.robust.backward * self.robust.pool(&backward);
+        }
+        Ok(score)
+    }
+
+    fn residuals(&self, candidate: &Candidate) -> anyhow::Result<Option<Vec<(Vec3, f32)>>> {
+        let sdf = candidate.sdf()?;
+        let inverse = candidate.transform().inverse();
+        Ok(Some(
+            self.target
+                .iter()
+                .map(|p| (*p, sdf.distance(inverse.apply(*p)).powi(2)))
+                .collect(),
+        ))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Surface {
+    target: Vec<Vec3>,
+    bvh: Option<Bvh>,
+    positions: Vec<Vec3>,
+    robust: Robust,
+}
+
+impl Surface {
+    pub fn new(robust: Robust) -> Self {
+        Self {
+            robust,
+            ..Self::default()
+        }
+    }
+}
+
+impl Scorer for Surface {
+    fn prepare(&mut self, target: &Mesh) {
+        self.target = target.surface_samples(SURFACE_SAMPLES);
+        self.bvh = Some(Bvh::new(target)).filter(|bvh| !bvh.is_empty());
+        self.positions = target.positions.clone();
+    }
+
+    fn score(&self, candidate: &Candidate) -> anyhow::Result<f32> {
+        let mesh = candidate.mesh()?;
+        let bvh = Bvh::new(mesh);
+        let forward = if bvh.is_empty() {
+            nearest_distances(&self.target, &mesh.positions)
+        } else {
+            self.target
+                .iter()
+                .map(|p| bvh.distance_squared(*p))
+                .collect()
+        };
+
+        let samples = mesh.surface_samples(SURFACE_SAMPLES);
+        let backward = match &self.bvh {
+            Some(target) => samples
+                .iter()
+                .map(|p| target.distance_squared(*p))
+                .collect(),
+            None => nearest_distances(&samples, &self.positions),
+        };
+
+        Ok(self.robust.forward * self.robust.pool(&forward)
+            + self.robust.backward * self.robust.pool(&backward))
+    }
+
+    fn residuals(&self, candidate: &Candidate) -> anyhow::Result<Option<Vec<(Vec3, f32)>>> {
+        let bvh = Bvh::new(candidate.mesh()?);
+        Ok(Some(
+            self.target
+                .iter()
+                .map(|p| (*p, bvh.distance_squared(*p)))
+                .collect(),
+        ))
+    }
+}
+
+/// One minus volumetric IoU, voxelized or sampled
+#[derive(Clone, Debug, Default)]
+pub struct Iou {
+    target: Mesh,
+    sampled: bool,
+}
+
+impl Iou {
+    pub fn voxel() -> Self {
+        Self::default()
+    }
+
+    pub fn sampled() -> Self {
+        Self {
+            sampled: true,
+            ..Self::default()
+        }
+    }
+}
+
+impl Scorer for Iou {
+    fn prepare(&mut self, target: &Mesh) {
+        self.target = target.clone();
+    }
+
+    fn score(&self, candidate: &Candidate) -> anyhow::Result<f32> {
+        let mesh = candidate.mesh()?;
+        let iou = if self.sampled {
+            sampled_iou(&self.target, mesh, IOU_SAMPLES)
+        } else {
+            voxel_iou(&self.target, mesh, VOXEL_RESOLUTION)
+        };
+        // open meshes have no volume to compare, rank them last
+        Ok(iou.map_or(f32::INFINITY, |iou| 1.0 - iou))
+    }
+}
+
+/// Penalises long programs, one per primitive
+#[derive(Clone, Debug, Default)]
+pub struct Complexity;
+
+impl Scorer for Complexity {
+    fn prepare(&mut self, _target: &Mesh) {}
+
+    fn score(&self, candidate: &Candidate) -> anyhow::Result<f32> {
+        Ok(candidate.kinds.len() as f32)
+    }
+
+    fn gradient(&self, candidate: &Candidate) -> anyhow::Result<Option<Vec<Vec3>>> {
+        Ok(Some(vec![Vec3::ZERO; candidate.mesh()?.positions.len()]))
+    }
+}
+
+/// Weighted sum of scorers
+#[derive(Default)]
+pub struct Weighted(pub Vec<(f32, Box<dyn Scorer>)>);
+
+impl Scorer for Weighted {
+    fn prepare(&mut self, target: &Mesh) {
+        for (_, scorer) in &mut self.0 {
+            scorer.prepare(target);
+        }
+    }
+
+    fn score(&self, candidate: &Candidate) -> anyhow::Result<f32> {
+        let mut total = 0.0;
+        for (weight, scorer) in &self.0 {
+            total += weight * scorer.score(candidate)?;
+        }
+        Ok(total)
+    }
+
+    fn gradient(&self, candidate: &Candidate) -> anyhow::Result<Option<Vec<Vec3>>> {
+        let mut total: Option<Vec<Vec3>> = None;
+        for (weight, scorer) in &self.0 {
+            let Some(grad) = scorer.gradient(candidate)? else {
+                return Ok(None);
+            };
+            match &mut total {
+                Some(total) => {
+                    for (t, g) in total.iter_mut().zip(grad) {
+                        *t += *weight * g;
+                    }
+                }
+                None => total = Some(grad.into_iter().map(|g| *weight * g).collect()),
+            }
+        }
+        Ok(total)
+    }
+
+    fn residuals(&self, candidate: &Candidate) -> anyhow::Result<Option<Vec<(Vec3, f32)>>> {
+        let mut total: Option<Vec<(Vec3, f32)>> = None;
+        for (weight, scorer) in &self.0 {
+            let Some(residuals) = scorer.residuals(candidate)? else {
+                continue;
+            };
+            match &mut total {
+                // only terms over the same target points can be summed
+                Some(total) if total.len() != residuals.len() => return Ok(None),
+                Some(total) => {
+                    for (t, (_, r)) in total.iter_mut().zip(residuals) {
+                        t.1 += weight * r;
+                    }
+                }
+                None => {
+                    total = Some(
+                        residuals
+                            .into_iter()
+                            .map(|(p, r)| (p, weight * r))
+                            .collect(),
+                    )
+                }
+            }
+        }
+        Ok(total)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Term {
+    Metric(Metric, Robust),
+    Complexity,
+}
+
+impl Term {
+    pub fn scorer(&self) -> Box<dyn Scorer> {
+        match self {
+            Term::Metric(Metric::Chamfer, robust) => Box::new(Chamfer::new(*robust)),
+            Term::Metric(Metric::Surface, robust) => Box::new(Surface::new(*robust)),
+            Term::Metric(Metric::Iou, _) => Box::new(Iou::voxel()),
+            Term::Metric(Metric::SampledIou, _) => Box::new(Iou::sampled()),
+            Term::Metric(Metric::Sdf, robust) => Box::new(Sdf::new(*robust)),
+            Term::Complexity => Box::new(Complexity),
+        }
+    }
+}
+
+/// Options of the distance metrics, e.g. `trim=0.9,huber=1,backward=0.2`
+fn parse_robust(options: &str) -> anyhow::Result<Robust> {
+    let mut robust = Robust::default();
+    for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected key=value: {option}"))?;
+        let value: f32 = value.trim().parse()?;
+        match key.trim() {
+            "trim" => robust.reduce = Reduce::Trimmed(value),
+            "percentile" => robust.reduce = Reduce::Percentile(value),
+            "huber" => robust.loss = Loss::Huber(value),
+            "truncate" => robust.loss = Loss::Truncated(value),
+            "forward" => robust.forward = value,
+            "backward" => robust.backward = value,
+            key => Err(anyhow!("unknown option: {key}"))?,
+        }
+    }
+    Ok(robust)
+}
+
+/// Scorer selection such as `surface`, `chamfer(trim=0.95,backward=0.2)` or
+/// `chamfer+0.5*iou+0.1*complexity`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreSpec(pub Vec<(f32, Term)>);
+
+impl Default for ScoreSpec {
+    fn default() -> Self {
+        Self(vec![(
+            1.0,
+            Term::Metric(Metric::Chamfer, Robust::default()),
+        )])
+    }
+}
+
+impl FromStr for ScoreSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut terms = vec![];
+        for term in s.split('+') {
+            let (weight, name) = match term.split_once('*') {
+                Some((weight, name)) => (weight.trim().parse()?, name.trim()),
+                None => (1.0, term.trim()),
+            };
+            let (name, options) = match name.split_once('(') {
+                Some((name, options)) => {
+                    let options = options
+                        .strip_suffix(')')
+                        .ok_or_else(|| anyhow!("missing `)` in {term}"))?;
+                    (name.trim(), Some(options))
+                }
+                None => (name, None),
+            };
+            let metric = match name {
+                "complexity" => None,
+                name => {
+                    Some(Metric::from_str(name, true).map_err(|e| anyhow!("unknown scorer: {e}"))?)
+                }
+            };
+            let term = match (metric, options) {
+                (Some(metric @ (Metric::Chamfer | Metric::Surface | Metric::Sdf)), options) => {
+                    Term::Metric(metric, parse_robust(options.unwrap_or_default())?)
+                }
+                (Some(metric), None) => Term::Metric(metric, Robust::default()),
+                (None, None) => Term::Complexity,
+                (_, Some(_)) => Err(anyhow!("`{name}` takes no options"))?,
+            };
+            terms.push((weight, term));
+        }
+        Ok(Self(terms))
+    }
+}
+
+impl ScoreSpec {
+    pub fn build(&self) -> Box<dyn Scorer> {
+        match self.0.as_slice() {
+            [(weight, term)] if *weight == 1.0 => term.scorer(),
+            terms => Box::new(Weighted(
+                terms.iter().map(|(w, t)| (*w, t.scorer())).collect(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mesh::tests::cuboid, transform::rotation_from_degrees};
+
+    const CUBE: [f32; 10] = [2.0, 2.0, 2.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0];
+
+    fn sdf_scorer(target: &Mesh) -> Sdf {
+        let mut scorer = Sdf::new(Robust::default());
+        scorer.prepare(target);
+        scorer
+    }
+
+    #[test]
+    fn sdf_scores_its_own_surface_best() {
+        // a dense target, the backward term measures to its points
+        let target = Mesh {
+            positions: cuboid(Vec3::ZERO, Vec3::splat(2.0)).surface_samples(2000),
+            triangles: vec![],
+        };
+        let scorer = sdf_scorer(&target);
+        let exact = scorer.score(&Candidate::new(&[0], &CUBE)).unwrap();
+        let mut bigger = CUBE;
+        bigger[0] = 3.0;
+        let worse = scorer.score(&Candidate::new(&[0], &bigger)).unwrap();
+        assert!(exact < worse, "{exact} {worse}");
+        let residuals = scorer
+            .residuals(&Candidate::new(&[0], &CUBE))
+            .unwrap()
+            .unwrap();
+        assert!(residuals.iter().all(|(_, r)| *r < 1e-10));
+    }
+
+    #[test]
+    fn sdf_follows_a_moved_candidate() {
+        let transform = Rigid {
+            rotation: rotation_from_degrees(0.0, 0.0, 30.0),
+            translation: Vec3::new(5.0, 0.0, 0.0),
+        };
+        let target = cuboid(Vec3::ZERO, Vec3::splat(2.0));
+        let here = sdf_scorer(&target)
+            .score(&Candidate::new(&[0], &CUBE))
+            .unwrap();
+
+        let scorer = sdf_scorer(&target.transformed(&transform));
+        let moved = Candidate::new(&[0], &CUBE).with_transform(transform);
+        let there = scorer.score(&moved).unwrap();
+        assert!((here - there).abs() < 1e-4, "{here} {there}");
+        assert!(scorer.score(&Candidate::new(&[0], &CUBE)).unwrap() > there + 1.0);
+    }
+
+    #[test]
+    fn empty_programs_rank_last() {
+        let scorer = sdf_scorer(&cuboid(Vec3::ZERO, Vec3::ONE));
+        assert_eq!(
+            scorer.score(&Candidate::new(&[3], &CUBE)).unwrap(),
+            f32::INFINITY
+        );
+    }
+}
@@ -0,0 +1,367 @@
+use anyhow::anyhow;
+use rand::prelude::*;
+use rerun::external::glam::{BVec3, Quat, Vec2, Vec3};
+
+use crate::{
+    bvh::{Aabb, Bvh},
+    mesh::{params_to_mesh, Mesh},
+    transform::rotation_from_degrees,
+};
+
+/// A primitive in its own frame, as µcad builds it: cubes and spheres centred
+/// on the origin, cylinders standing on the xy plane along z
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Solid {
+    Cube { half: Vec3 },
+    Sphere { radius: f32 },
+    Cylinder { radius: f32, height: f32 },
+}
+
+impl Solid {
+    /// Exact signed distance, negative inside
+    pub fn distance(&self, p: Vec3) -> f32 {
+        match *self {
+            Solid::Cube { half } => {
+                let q = p.abs() - half;
+                q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+            }
+            Solid::Sphere { radius } => p.length() - radius,
+            Solid::Cylinder { radius, height } => {
+                let half = height * 0.5;
+                let q = Vec2::new(p.truncate().length() - radius, (p.z - half).abs() - half);
+                q.max(Vec2::ZERO).length() + q.max_element().min(0.0)
+            }
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let (min, max) = match *self {
+            Solid::Cube { half } => (-half, half),
+            Solid::Sphere { radius } => (Vec3::splat(-radius), Vec3::splat(radius)),
+            Solid::Cylinder { radius, height } => (
+                Vec3::new(-radius, -radius, 0.0),
+                Vec3::new(radius, radius, height),
+            ),
+        };
+        Aabb { min, max }
+    }
+}
+
+/// A solid moved the way `generate::ucad` emits it, `.translate(t).rotate(r)`,
+/// so a local point `p` lands on `rotation * (p + translation)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Placed {
+    pub solid: Solid,
+    pub rotation: Quat,
+    pub translation: Vec3,
+}
+
+impl Placed {
+    pub fn distance(&self, p: Vec3) -> f32 {
+        self.solid
+            .distance(self.rotation.inverse() * p - self.translation)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let local = self.solid.bounds();
+        let mut aabb = Aabb::default();
+        for corner in 0..8 {
+            let mask = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+            let c = Vec3::select(mask, local.max, local.min);
+            aabb.grow(self.rotation * (c + self.translation));
+        }
+        aabb
+    }
+}
+
+/// Analytic distance field of a whole program. `&` binds tighter than `|`, so
+/// the chain is a union (min) of intersections (max); away from the surface
+/// that is a bound on the true distance rather than the distance itself
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProgramSdf {
+    pub groups: Vec<Vec<Placed>>,
+}
+
+impl ProgramSdf {
+    /// Kind 3 primitives are skipped and kind 4 ends the program, as in rendering
+    pub fn new(kinds: &[u8], params: &[f32]) -> anyhow::Result<Self> {
+        if kinds.len() * 10 != params.len() {
+            Err(anyhow!(
+                "tokens and parameters length do not match!: {}, {}",
+                kinds.len() * 10,
+                params.len()
+            ))?
+        }
+
+        let mut groups: Vec<Vec<Placed>> = vec![];
+        for (kind, p) in kinds.iter().zip(params.chunks_exact(10)) {
+            let solid = match kind {
+                0 => Solid::Cube {
+                    half: Vec3::new(p[0], p[1], p[2]) * 0.5,
+                },
+                1 => Solid::Sphere { radius: p[0] },
+                2 => Solid::Cylinder {
+                    radius: p[0] * 0.5,
+                    height: p[1],
+                },
+                3 => continue,
+                4 => break,
+                _ => Err(anyhow!("invalid token: {kind}"))?,
+            };
+            let placed = Placed {
+                solid,
+                rotation: rotation_from_degrees(p[6], p[7], p[8]),
+                translation: Vec3::new(p[3], p[4], p[5]),
+            };
+            match groups.last_mut() {
+                Some(group) if p[9] > 1.0 => group.push(placed),
+                _ => groups.push(vec![placed]),
+            }
+        }
+        Ok(Self { groups })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Negative inside, `f32::INFINITY` for an empty program
+    pub fn distance(&self, p: Vec3) -> f32 {
+        self.groups
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|placed| placed.distance(p))
+                    .fold(f32::NEG_INFINITY, f32::max)
+            })
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    pub fn contains(&self, p: Vec3) -> bool {
+        self.distance(p) <= 0.0
+    }
+
+    /// Central difference gradient, unit length wherever the field is a true distance
+    pub fn gradient(&self, p: Vec3) -> Vec3 {
+        const H: f32 = 1e-3;
+        Vec3::from_array(std::array::from_fn(|axis| {
+            let mut e = Vec3::ZERO;
+            e[axis] = H;
+            (self.distance(p + e) - self.distance(p - e)) / (2.0 * H)
+        }))
+    }
+
+    /// Points on the zero level set without meshing: random points in the
+    /// bounds pulled onto the surface by Newton steps, seeded so repeated calls
+    /// agree; fewer than `n` when some fail to converge
+    pub fn surface_samples(&self, n: usize) -> Vec<Vec3> {
+        let bounds = self.bounds();
+        if bounds.is_empty() {
+            return vec![];
+        }
+        let tolerance = bounds.size().length() * 1e-4;
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+        (0..n)
+            .filter_map(|_| {
+                let mut p = random_point(&bounds, &mut rng);
+                for _ in 0..8 {
+                    let d = self.distance(p);
+                    if d.abs() <= tolerance {
+                        return Some(p);
+                    }
+                    let g = self.gradient(p);
+                    p -= g * d / g.length_squared().max(f32::EPSILON);
+                }
+                None
+            })
+            .collect()
+    }
+
+    /// Box around every primitive, intersections can only be smaller
+    pub fn bounds(&self) -> Aabb {
+        self.groups
+            .iter()
+            .flatten()
+            .fold(Aabb::default(), |aabb, placed| aabb.union(&placed.bounds()))
+    }
+}
+
+fn random_point(bounds: &Aabb, rng: &mut impl Rng) -> Vec3 {
+    Vec3::from_array(std::array::from_fn(|axis| {
+        rng.random_range(bounds.min[axis]..=bounds.max[axis])
+    }))
+}
+
+/// Fraction of random points in the joint bounding box on which the analytic
+/// field and the rendered mesh agree about inside and outside, all of them
+/// when both are empty
+pub fn agreement(sdf: &ProgramSdf, mesh: &Mesh, samples: usize) -> f32 {
+    let bvh = Bvh::new(mesh);
+    let bounds = sdf.bounds().union(&Aabb::from_points(&mesh.positions));
+    if bounds.is_empty() {
+        return 1.0;
+    }
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+    let agree = (0..samples)
+        .filter(|_| {
+            let p = random_point(&bounds, &mut rng);
+            sdf.contains(p) == bvh.contains(p)
+        })
+        .count();
+    agree as f32 / samples.max(1) as f32
+}
+
+/// Whether two fields agree to within `tolerance`, and never closer than a
+/// thousandth of their joint bounding box, at random points in it, which for
+/// exact distances means the same solid however its parameters spell it
+pub fn same_shape(a: &ProgramSdf, b: &ProgramSdf, samples: usize, tolerance: f32) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return a.is_empty() == b.is_empty();
+    }
+    let bounds = a.bounds().union(&b.bounds());
+    if bounds.is_empty() {
+        return false;
+    }
+    let tolerance = tolerance.max(bounds.size().length() * 1e-3);
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+    (0..samples).all(|_| {
+        let p = random_point(&bounds, &mut rng);
+        (a.distance(p) - b.distance(p)).abs() <= tolerance
+    })
+}
+
+/// [`agreement`] of a program's analytic field with its µcad render
+pub fn cross_check(kinds: &[u8], params: &[f32], samples: usize) -> anyhow::Result<f32> {
+    let sdf = ProgramSdf::new(kinds, params)?;
+    let mesh = params_to_mesh(kinds, params)?;
+    Ok(agreement(&sdf, &mesh, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::tests::cuboid;
+
+    /// Parameters of one primitive, `union` above 1 intersects it with the one before
+    fn primitive(
+        size: [f32; 3],
+        translation: [f32; 3],
+        rotation: [f32; 3],
+        union: f32,
+    ) -> [f32; 10] {
+        let [sx, sy, sz] = size;
+        let [tx, ty, tz] = translation;
+        let [rx, ry, rz] = rotation;
+        [sx, sy, sz, tx, ty, tz, rx, ry, rz, union]
+    }
+
+    #[test]
+    fn solid_distances() {
+        let cube = Solid::Cube { half: Vec3::ONE };
+        assert_eq!(cube.distance(Vec3::ZERO), -1.0);
+        assert_eq!(cube.distance(Vec3::new(3.0, 0.0, 0.0)), 2.0);
+        assert!((cube.distance(Vec3::new(2.0, 2.0, 0.0)) - 2.0f32.sqrt()).abs() < 1e-6);
+
+        let sphere = Solid::Sphere { radius: 2.0 };
+        assert_eq!(sphere.distance(Vec3::new(0.0, 3.0, 0.0)), 1.0);
+
+        let cylinder = Solid::Cylinder {
+            radius: 1.0,
+            height: 2.0,
+        };
+        assert_eq!(cylinder.distance(Vec3::new(0.0, 0.0, 1.0)), -1.0);
+        assert_eq!(cylinder.distance(Vec3::new(0.0, 0.0, 3.0)), 1.0);
+        assert_eq!(cylinder.distance(Vec3::new(3.0, 0.0, 1.0)), 2.0);
+        assert_eq!(cylinder.bounds().min, Vec3::new(-1.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn placed_solids_translate_then_rotate() {
+        let placed = Placed {
+            solid: Solid::Sphere { radius: 1.0 },
+            rotation: rotation_from_degrees(0.0, 0.0, 90.0),
+            translation: Vec3::new(2.0, 0.0, 0.0),
+        };
+        // the centre moves to x = 2, then turns onto y = 2
+        assert!(placed.distance(Vec3::new(0.0, 2.0, 0.0)) + 1.0 < 1e-5);
+        let bounds = placed.bounds();
+        assert!((bounds.center() - Vec3::new(0.0, 2.0, 0.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn programs_are_unions_of_intersections() {
+        let a = primitive([2.0; 3], [0.0; 3], [0.0; 3], 0.0);
+        let b = primitive([2.0; 3], [1.0, 0.0, 0.0], [0.0; 3], 0.0);
+        let kinds = [0, 0];
+
+        let union = ProgramSdf::new(&kinds, &[a, b].concat()).unwrap();
+        assert_eq!(union.groups.len(), 2);
+        assert!(union.contains(Vec3::new(1.8, 0.0, 0.0)));
+        assert!(union.contains(Vec3::new(-0.8, 0.0, 0.0)));
+
+        let b = primitive([2.0; 3], [1.0, 0.0, 0.0], [0.0; 3], 2.0);
+        let intersection = ProgramSdf::new(&kinds, &[a, b].concat()).unwrap();
+        assert_eq!(intersection.groups.len(), 1);
+        assert!(intersection.contains(Vec3::new(0.5, 0.0, 0.0)));
+        assert!(!intersection.contains(Vec3::new(1.8, 0.0, 0.0)));
+        assert!(!intersection.contains(Vec3::new(-0.8, 0.0, 0.0)));
+
+        // kind 3 is skipped and kind 4 ends the program
+        let skipped = ProgramSdf::new(&[3, 0, 4, 1], &[a, a, a, a].concat()).unwrap();
+        assert_eq!(skipped.groups.len(), 1);
+        assert!(ProgramSdf::new(&[0], &a[..9]).is_err());
+        assert!(ProgramSdf::new(&[9], &a).is_err());
+    }
+
+    #[test]
+    fn empty_programs() {
+        let empty = ProgramSdf::default();
+        assert_eq!(empty.distance(Vec3::ZERO), f32::INFINITY);
+        assert!(empty.bounds().is_empty());
+        assert!(empty.surface_samples(10).is_empty());
+        assert_eq!(agreement(&empty, &Mesh::default(), 100), 1.0);
+        assert!(same_shape(&empty, &empty, 100, 0.0));
+
+        let cube = ProgramSdf::new(&[0], &primitive([1.0; 3], [0.0; 3], [0.0; 3], 0.0)).unwrap();
+        assert!(!same_shape(&cube, &empty, 100, 0.0));
+        assert!(agreement(&empty, &cuboid(Vec3::ZERO, Vec3::ONE), 100) < 1.0);
+    }
+
+    #[test]
+    fn same_shape_however_it_is_spelled() {
+        let a = ProgramSdf::new(&[0], &primitive([1.0, 2.0, 3.0], [0.0; 3], [0.0; 3], 0.0));
+        // the same box turned a quarter about z, with x and y swapped
+        let b = ProgramSdf::new(
+            &[0],
+            &primitive([2.0, 1.0, 3.0], [0.0; 3], [0.0, 0.0, 90.0], 0.0),
+        );
+        let c = ProgramSdf::new(&[0], &primitive([1.0, 2.0, 3.1], [0.0; 3], [0.0; 3], 0.0));
+        let (a, b, c) = (a.unwrap(), b.unwrap(), c.unwrap());
+        assert!(same_shape(&a, &b, 1000, 0.0));
+        assert!(!same_shape(&a, &c, 1000, 0.0));
+        // the ends of c are 0.05 out
+        assert!(same_shape(&a, &c, 1000, 0.1));
+    }
+
+    #[test]
+    fn field_agrees_with_a_matching_mesh() {
+        let sdf = ProgramSdf::new(&[0], &primitive([2.0; 3], [0.5; 3], [0.0; 3], 0.0)).unwrap();
+        let mesh = cuboid(Vec3::splat(-0.5), Vec3::splat(1.5));
+        assert!(agreement(&sdf, &mesh, 1000) > 0.99);
+        let smaller = cuboid(Vec3::splat(-0.5), Vec3::splat(1.0));
+        assert!(agreement(&sdf, &smaller, 1000) < 0.9);
+    }
+
+    #[test]
+    fn surface_samples_lie_on_the_surface() {
+        let sdf =
+            ProgramSdf::new(&[1], &primitive([1.5, 0.0, 0.0], [1.0; 3], [0.0; 3], 0.0)).unwrap();
+        let samples = sdf.surface_samples(200);
+        assert!(samples.len() > 150);
+        assert_eq!(samples, sdf.surface_samples(200));
+        for p in samples {
+            assert!((p.distance(Vec3::ONE) - 1.5).abs() < 1e-3, "{p}");
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    io::Write as _,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::prelude::*;
+
+use crate::{
+    analysis::Prefilter,
+    grid::{Grid, Refine},
+    mesh::Renderer,
+    sample::SampleConfig,
+    score::{Candidate, Scorer},
+    sdf::{self, ProgramSdf},
+};
+
+/// Probe points [`distinct`] compares two primitives at
+const SHAPE_SAMPLES: usize = 64;
+
+/// Scores many candidate programs on worker threads
+#[derive(Clone, Copy)]
+pub struct Evaluator<'a> {
+    pub scorer: &'a dyn Scorer,
+    pub prefilter: Option<&'a Prefilter>,
+    pub renderer: Renderer,
+    /// workers, 0 for one per core
+    pub threads: usize,
+}
+
+impl Evaluator<'_> {
+    fn threads(&self, count: usize) -> usize {
+        let threads = match self.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        threads.clamp(1, count.max(1))
+    }
+
+    /// Score of the program `program(i)` for every `i` below `count`, `None`
+    /// where it was rejected by the prefilter, failed to render or score, or
+    /// was never reached because `cancel` was set. Workers take the next index
+    /// from a shared counter so a few slow renders don't hold up a fixed share,
+    /// and the calling thread draws a progress bar on stderr meanwhile.
+    pub fn score_all(
+        &self,
+        count: usize,
+        program: impl Fn(usize) -> (Vec<u8>, Vec<f32>) + Sync,
+        cancel: &AtomicBool,
+    ) -> Vec<Option<f32>> {
+        let next = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+        let mut scores = vec![None; count];
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads(count))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut scored = vec![];
+                        while !cancel.load(Ordering::Relaxed) {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            if i >= count {
+                                break;
+                            }
+                            let (kinds, params) = program(i);
+                            if let Some(score) = self.score(&kinds, &params) {
+                                scored.push((i, score));
+                            }
+                            done.fetch_add(1, Ordering::Relaxed);
+                        }
+                        scored
+                    })
+                })
+                .collect();
+
+            let progress = Progress::new(count);
+            while !workers.iter().all(|worker| worker.is_finished()) {
+                progress.draw(done.load(Ordering::Relaxed));
+                thread::sleep(Duration::from_millis(100));
+            }
+            progress.finish(done.load(Ordering::Relaxed));
+
+            for worker in workers {
+                for (i, score) in worker.join().expect("search worker panicked") {
+                    scores[i] = Some(score);
+                }
+            }
+        });
+        scores
+    }
+
+    /// Kind, parameters and score of every candidate drawn from `grid` by
+    /// `sampling` for each of `kinds` that could be scored, best first.
+    /// `program` puts a candidate into the rest of the program. With `refine`
+    /// the best cells of each level are searched again on a finer grid, and
+    /// their candidates added, one cell per basin. The random samplers draw every cell from its
+    /// own seed, all derived from `seed`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn search(
+        &self,
+        grid: &Grid,
+        sampling: &SampleConfig,
+        kinds: &[u8],
+        refine: &Refine,
+        seed: u64,
+        program: impl Fn(u8, &[f32; 10]) -> (Vec<u8>, Vec<f32>) + Sync,
+        cancel: &AtomicBool,
+    ) -> Vec<(u8, [f32; 10], f32)> {
+        let mut cells: Vec<(u8, Grid)> = kinds.iter().map(|&kind| (kind, *grid)).collect();
+        let mut scored: Vec<(u8, [f32; 10], f32)> = vec![];
+        let mut seeds = rand::rngs::SmallRng::seed_from_u64(seed);
+        for level in 0..=refine.levels {
+            let candidates: Vec<(u8, [f32; 10], usize)> = cells
+                .iter()
+                .enumerate()
+                .flat_map(|(cell, (kind, grid))| {
+                    let kind = *kind;
+                    sampling
+                        .candidates(grid, seeds.random())
+                        .into_iter()
+                        .map(move |params| (kind, params, cell))
+                })
+                .collect();
+            if level > 0 {
+                println!(
+                    "refinement {level}: {} candidates around {} cells",
+                    candidates.len(),
+                    cells.len()
+                );
+            }
+            let scores = self.score_all(
+                candidates.len(),
+                |i| program(candidates[i].0, &candidates[i].1),
+                cancel,
+            );
+            let mut ranked: Vec<_> = candidates
+                .into_iter()
+                .zip(scores)
+                .filter_map(|(candidate, score)| Some((candidate, score?)))
+                .collect();
+            ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            let previous = scored.iter().map(|c| c.2).reduce(f32::min);
+            scored.extend(
+                ranked
+                    .iter()
+                    .map(|&((kind, params, _), score)| (kind, params, score)),
+            );
+            let stalled = match (previous, ranked.first()) {
+                (Some(previous), Some((_, best))) => refine.stalled(previous, *best),
+                _ => false,
+            };
+            if stalled || cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            // cells a step apart, diagonals included, share a basin, and
+            // refining each of them would spend `keep` on one minimum
+            let shapes = ranked.iter().map(|((kind, params, _), _)| (*kind, params));
+            cells = distinct_indices(shapes, refine.keep, grid.resolution() * 1.5)
+                .into_iter()
+                .map(|i| {
+                    let ((kind, params, cell), _) = ranked[i];
+                    (kind, cells[cell].1.around(&params))
+                })
+                .collect();
+            if cells.is_empty() {
+                break;
+            }
+        }
+        scored.sort_by(|a, b| a.2.total_cmp(&b.2));
+        scored
+    }
+
+    fn score(&self, kinds: &[u8], params: &[f32]) -> Option<f32> {
+        let candidate = Candidate::new(kinds, params).with_renderer(self.renderer);
+        if let Some(prefilter) = self.prefilter {
+            if !prefilter.accepts(candidate.mesh().ok()?) {
+                return None;
+            }
+        }
+        self.scorer.score(&candidate).ok()
+    }
+}
+
+/// The first `k` of `scored` whose primitive's field differs by more than a
+/// quarter of `spacing` somewhere from every one kept before it, so rotated
+/// spheres and the neighbours a refined cell finds around one optimum don't
+/// crowd out the runners-up, while the coarse grid's neighbours, half a step
+/// apart or more, stay apart
+pub fn distinct(
+    scored: &[(u8, [f32; 10], f32)],
+    k: usize,
+    spacing: f32,
+) -> Vec<(u8, [f32; 10], f32)> {
+    let shapes = scored.iter().map(|(kind, params, _)| (*kind, params));
+    distinct_indices(shapes, k, spacing * 0.25)
+        .into_iter()
+        .map(|i| scored[i])
+        .collect()
+}
+
+/// Indices of the first `k` primitives whose field differs by more than
+/// `tolerance` somewhere from every one kept before it
+fn distinct_indices<'a>(
+    shapes: impl IntoIterator<Item = (u8, &'a [f32; 10])>,
+    k: usize,
+    tolerance: f32,
+) -> Vec<usize> {
+    let mut kept: Vec<(ProgramSdf, usize)> = vec![];
+    for (i, (kind, params)) in shapes.into_iter().enumerate() {
+        if kept.len() >= k {
+            break;
+        }
+        let Ok(shape) = ProgramSdf::new(&[kind], params) else {
+            continue;
+        };
+        if !kept
+            .iter()
+            .any(|(other, _)| sdf::same_shape(&shape, other, SHAPE_SAMPLES, tolerance))
+        {
+            kept.push((shape, i));
+        }
+    }
+    kept.into_iter().map(|(_, i)| i).collect()
+}
+
+/// `[#####.....] done/total percent eta` on one line of stderr
+struct Progress {
+    total: usize,
+    start: Instant,
+}
+
+impl Progress {
+    const WIDTH: usize = 30;
+
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            start: Instant::now(),
+        }
+    }
+
+    fn draw(&self, done: usize) {
+        let fraction = done as f32 / self.total.max(1) as f32;
+        let filled = (fraction * Self::WIDTH as f32) as usize;
+        let eta = match done {
+            0 => "?".into(),
+            _ => {
+                let elapsed = self.start.elapsed().as_secs_f32();
+                format_duration(elapsed / fraction - elapsed)
+            }
+        };
+        eprint!(
+            "\r[{}{}] {done}/{} {:>3.0}% eta {eta}  ",
+            "#".repeat(filled),
+            ".".repeat(Self::WIDTH - filled.min(Self::WIDTH)),
+            self.total,
+            fraction * 100.0
+        );
+        let _ = std::io::stderr().flush();
+    }
+
+    fn finish(&self, done: usize) {
+        self.draw(done);
+        eprintln!("in {}", format_duration(self.start.elapsed().as_secs_f32()));
+    }
+}
+
+fn format_duration(seconds: f32) -> String {
+    let seconds = seconds.round() as u64;
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::Axis, mesh::Mesh};
+
+    /// Two minima along x, the one at -1.4 shallower than its rival's
+    /// neighbours
+    struct Basins;
+
+    impl Scorer for Basins {
+        fn prepare(&mut self, _target: &Mesh) {}
+
+        fn score(&self, candidate: &Candidate) -> anyhow::Result<f32> {
+            let x = candidate.params[3];
+            Ok((x - 1.4).abs().min((x + 1.4).abs() + 0.45))
+        }
+    }
+
+    fn cube(size: f32, x: f32, rz: f32, score: f32) -> (u8, [f32; 10], f32) {
+        (0, [size, size, size, x, 0.0, 0.0, 0.0, 0.0, rz, 0.0], score)
+    }
+
+    #[test]
+    fn near_duplicates_collapse_within_the_spacing() {
+        let scored = [
+            cube(1.0, 0.0, 0.0, 0.1),
+            // a quarter turn of a cube is the same cube
+            cube(1.0, 0.0, 90.0, 0.2),
+            // a refined neighbour a tenth of a step along
+            cube(1.0, 0.05, 0.0, 0.3),
+            // the next step of the coarse grid
+            cube(1.0, 0.5, 0.0, 0.4),
+            cube(1.5, 0.0, 0.0, 0.5),
+        ];
+        let kept = distinct(&scored, 10, 0.5);
+        let scores: Vec<f32> = kept.iter().map(|c| c.2).collect();
+        assert_eq!(scores, [0.1, 0.4, 0.5]);
+        assert_eq!(distinct(&scored, 2, 0.5).len(), 2);
+        // without a spacing only exact matches collapse
+        assert_eq!(distinct(&scored, 10, 0.0).len(), 4);
+    }
+
+    #[test]
+    fn refinement_keeps_separate_basins() {
+        let fixed = Axis::new(0.0, 0.0, 1);
+        let grid = Grid {
+            size: [Axis::new(1.0, 1.0, 1); 3],
+            translation: [Axis::new(-2.0, 2.0, 9), fixed, fixed],
+            rotation: [fixed; 3],
+        };
+        let refine = Refine {
+            levels: 1,
+            keep: 2,
+            tolerance: 0.0,
+        };
+        let evaluator = Evaluator {
+            scorer: &Basins,
+            prefilter: None,
+            renderer: Renderer::default(),
+            threads: 1,
+        };
+        let scored = evaluator.search(
+            &grid,
+            &SampleConfig::default(),
+            &[0],
+            &refine,
+            0,
+            |kind, params| (vec![kind], params.to_vec()),
+            &AtomicBool::new(false),
+        );
+        // 1.0 ranks second on the coarse grid, but sits in the best's basin
+        let refined = |x: f32| (x * 2.0).fract() != 0.0;
+        assert!(scored.iter().any(|c| c.1[3] > 0.0 && refined(c.1[3])));
+        assert!(scored.iter().any(|c| c.1[3] < 0.0 && refined(c.1[3])));
+        assert!(!scored
+            .iter()
+            .any(|c| (0.75..1.25).contains(&c.1[3]) && refined(c.1[3])));
+    }
+}
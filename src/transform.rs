@@ -0,0 +1,273 @@
+use std::{fmt, ops::Mul};
+
+use clap::ValueEnum;
+use rerun::external::glam::{EulerRot, Quat, Vec3};
+
+use crate::{bvh::Aabb, mesh::Mesh, optimize::ParamBounds};
+
+/// Rotation for µcad's `rotate(x, y, z)`, which turns about x first, then y, then z
+pub fn rotation_from_degrees(x: f32, y: f32, z: f32) -> Quat {
+    Quat::from_euler(
+        EulerRot::ZYX,
+        z.to_radians(),
+        y.to_radians(),
+        x.to_radians(),
+    )
+}
+
+/// Inverse of [`rotation_from_degrees`]
+pub fn rotation_to_degrees(rotation: Quat) -> Vec3 {
+    let (z, y, x) = rotation.to_euler(EulerRot::ZYX);
+    Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees())
+}
+
+/// `p -> rotation * p + translation`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rigid {
+    pub rotation: Quat,
+    pub translation: Vec3,
+}
+
+impl Default for Rigid {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Rigid {
+    pub const IDENTITY: Self = Self {
+        rotation: Quat::IDENTITY,
+        translation: Vec3::ZERO,
+    };
+
+    pub fn apply(&self, p: Vec3) -> Vec3 {
+        self.rotation * p + self.translation
+    }
+
+    pub fn inverse(&self) -> Self {
+        let rotation = self.rotation.inverse();
+        Self {
+            rotation,
+            translation: -(rotation * self.translation),
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.rotation.abs_diff_eq(Quat::IDENTITY, 1e-6)
+            && self.translation.abs_diff_eq(Vec3::ZERO, 1e-6)
+    }
+}
+
+/// `(a * b).apply(p) == a.apply(b.apply(p))`
+impl Mul for Rigid {
+    type Output = Rigid;
+
+    fn mul(self, rhs: Rigid) -> Rigid {
+        Rigid {
+            rotation: (self.rotation * rhs.rotation).normalize(),
+            translation: self.rotation * rhs.translation + self.translation,
+        }
+    }
+}
+
+impl fmt::Display for Rigid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = rotation_to_degrees(self.rotation);
+        let t = self.translation;
+        write!(
+            f,
+            "rotate(x = {}deg, y = {}deg, z = {}deg).translate(x = {}mm, y = {}mm, z = {}mm)",
+            r.x, r.y, r.z, t.x, t.y, t.z
+        )
+    }
+}
+
+/// `p -> p * scale + offset`, from target units into the space the search runs in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scaling {
+    pub scale: f32,
+    pub offset: Vec3,
+}
+
+impl Default for Scaling {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Scaling {
+    pub const IDENTITY: Self = Self {
+        scale: 1.0,
+        offset: Vec3::ZERO,
+    };
+
+    pub fn apply(&self, p: Vec3) -> Vec3 {
+        p * self.scale + self.offset
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            scale: 1.0 / self.scale,
+            offset: -self.offset / self.scale,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        (self.scale - 1.0).abs() < 1e-6 && self.offset.abs_diff_eq(Vec3::ZERO, 1e-6)
+    }
+
+    pub fn mesh(&self, mesh: &Mesh) -> Mesh {
+        Mesh {
+            positions: mesh.positions.iter().map(|p| self.apply(*p)).collect(),
+            triangles: mesh.triangles.clone(),
+        }
+    }
+
+    /// Uniform scale and offset putting `points`' bounding box in the middle of
+    /// a cube of side `side` centred on `center`
+    pub fn fit(points: &[Vec3], center: Vec3, side: f32) -> Self {
+        let aabb = Aabb::from_points(points);
+        let extent = aabb.size().max_element();
+        if points.is_empty() || extent <= f32::EPSILON {
+            return Self {
+                scale: 1.0,
+                offset: center - aabb.center(),
+            };
+        }
+        let scale = side / extent;
+        Self {
+            scale,
+            offset: center - aabb.center() * scale,
+        }
+    }
+
+    /// Program parameters found in scaled space, back in target units, with the
+    /// rigid transform found there; µcad places a primitive at
+    /// `rotate(translate(p))` about the origin, so sizes and translations
+    /// shrink by the scale and the offset moves onto the whole program
+    pub fn unscale_program(&self, params: &[f32], transform: &Rigid) -> (Vec<f32>, Rigid) {
+        let inverse = self.inverse();
+        let mut params = params.to_vec();
+        for primitive in params.chunks_exact_mut(10) {
+            for p in &mut primitive[..6] {
+                *p *= inverse.scale;
+            }
+        }
+        let transform = Rigid {
+            rotation: transform.rotation,
+            translation: transform.translation * inverse.scale + inverse.offset,
+        };
+        (params, transform)
+    }
+}
+
+impl fmt::Display for Scaling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let o = self.offset;
+        write!(
+            f,
+            "scale {} then translate ({}, {}, {})",
+            self.scale, o.x, o.y, o.z
+        )
+    }
+}
+
+/// Where a target is moved before searching
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Normalize {
+    /// search in the target's own units
+    #[default]
+    None,
+    /// centred on the origin, longest side 1
+    Unit,
+    /// centred in the translation range, longest side the largest primitive size
+    Range,
+}
+
+impl Normalize {
+    pub fn fit(&self, target: &Mesh, bounds: &ParamBounds) -> Scaling {
+        match self {
+            Normalize::None => Scaling::IDENTITY,
+            Normalize::Unit => Scaling::fit(&target.positions, Vec3::ZERO, 1.0),
+            Normalize::Range => {
+                let (low, high) = bounds.translation;
+                let center = Vec3::splat((low + high) * 0.5);
+                Scaling::fit(&target.positions, center, bounds.size.1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degrees_round_trip() {
+        let degrees = Vec3::new(30.0, -45.0, 120.0);
+        let back = rotation_to_degrees(rotation_from_degrees(degrees.x, degrees.y, degrees.z));
+        assert!((back - degrees).length() < 1e-3, "{back}");
+    }
+
+    #[test]
+    fn rotation_turns_about_x_first() {
+        let r = rotation_from_degrees(90.0, 90.0, 0.0);
+        // x takes y onto z, then y takes z onto x
+        assert!((r * Vec3::Y - Vec3::X).length() < 1e-6);
+    }
+
+    #[test]
+    fn rigid_inverse_and_composition() {
+        let a = Rigid {
+            rotation: rotation_from_degrees(10.0, 20.0, 30.0),
+            translation: Vec3::new(1.0, 2.0, 3.0),
+        };
+        let b = Rigid {
+            rotation: rotation_from_degrees(-40.0, 0.0, 15.0),
+            translation: Vec3::new(-2.0, 0.5, 0.0),
+        };
+        let p = Vec3::new(0.3, -1.2, 2.5);
+        assert!((a.inverse().apply(a.apply(p)) - p).length() < 1e-5);
+        assert!((a * a.inverse()).is_identity());
+        assert!(((a * b).apply(p) - a.apply(b.apply(p))).length() < 1e-5);
+        assert!(Rigid::IDENTITY.is_identity());
+    }
+
+    #[test]
+    fn scaling_inverse_and_fit() {
+        let points = [Vec3::new(1.0, 2.0, 3.0), Vec3::new(5.0, 4.0, 3.0)];
+        let scaling = Scaling::fit(&points, Vec3::splat(2.5), 2.0);
+        assert_eq!(scaling.scale, 0.5);
+        assert!((scaling.apply(points[0]) - Vec3::new(1.5, 2.0, 2.5)).length() < 1e-6);
+        assert!((scaling.apply(points[1]) - Vec3::new(3.5, 3.0, 2.5)).length() < 1e-6);
+        let p = Vec3::new(-0.7, 0.2, 9.0);
+        assert!((scaling.inverse().apply(scaling.apply(p)) - p).length() < 1e-5);
+        // a single point only moves
+        let single = Scaling::fit(&points[..1], Vec3::ZERO, 2.0);
+        assert_eq!(single.scale, 1.0);
+        assert!(single.apply(points[0]).length() < 1e-6);
+        assert!(Scaling::default().is_identity());
+    }
+
+    #[test]
+    fn unscaled_programs_place_points_in_target_units() {
+        let scaling = Scaling {
+            scale: 0.25,
+            offset: Vec3::new(1.0, -2.0, 0.5),
+        };
+        let params = [2.0, 3.0, 4.0, 0.5, 1.0, -1.0, 0.0, 0.0, 0.0, 0.0];
+        let transform = Rigid {
+            rotation: rotation_from_degrees(0.0, 30.0, 60.0),
+            translation: Vec3::new(0.3, 0.1, -0.2),
+        };
+        let (unscaled, back) = scaling.unscale_program(&params, &transform);
+        assert_eq!(unscaled[..6], [8.0, 12.0, 16.0, 2.0, 4.0, -4.0]);
+        assert_eq!(unscaled[6..], params[6..]);
+        // the primitive's origin lands where the inverse scaling puts it
+        let origin = |params: &[f32], transform: &Rigid| {
+            transform.apply(Vec3::new(params[3], params[4], params[5]))
+        };
+        let expected = scaling.inverse().apply(origin(&params, &transform));
+        assert!((origin(&unscaled, &back) - expected).length() < 1e-4);
+    }
+}
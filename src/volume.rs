@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use rand::prelude::*;
+use rerun::external::glam::{self, Vec3};
+
+use crate::{
+    bvh::{Aabb, Bvh},
+    mesh::Mesh,
+};
+
+/// Cells along the longest axis of the shared bounding box
+pub const VOXEL_RESOLUTION: usize = 32;
+/// Points drawn in the shared bounding box for [`sampled_iou`]
+pub const IOU_SAMPLES: usize = 8192;
+
+/// Every edge is shared by exactly two triangles
+pub fn is_watertight(mesh: &Mesh) -> bool {
+    if mesh.triangles.is_empty() {
+        return false;
+    }
+
+    let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+    for &[a, b, c] in &mesh.triangles {
+        for (u, v) in [(a, b), (b, c), (c, a)] {
+            *edges.entry((u.min(v), u.max(v))).or_default() += 1;
+        }
+    }
+    edges.values().all(|&n| n == 2)
+}
+
+fn check_watertight(mesh: &Mesh, what: &str) -> anyhow::Result<()> {
+    if !is_watertight(mesh) {
+        Err(anyhow!("{what} mesh is not watertight"))?
+    }
+    Ok(())
+}
+
+/// Occupancy of a regular grid, cell `(x, y, z)` is at `x + dims[0] * (y + dims[1] * z)`
+#[derive(Clone, Debug)]
+pub struct VoxelGrid {
+    pub origin: Vec3,
+    pub cell: f32,
+    pub dims: [usize; 3],
+    pub occupied: Vec<bool>,
+}
+
+impl VoxelGrid {
+    /// Grid covering `bounds` with `resolution` cells along its longest axis
+    pub fn new(bounds: &Aabb, resolution: usize) -> Self {
+        let size = bounds.size();
+        let cell = (size.max_element() / resolution.max(1) as f32).max(f32::EPSILON);
+        let dims = (size / cell)
+            .ceil()
+            .max(Vec3::ONE)
+            .to_array()
+            .map(|d| d as usize);
+        Self {
+            origin: bounds.min,
+            cell,
+            dims,
+            occupied: vec![false; dims[0] * dims[1] * dims[2]],
+        }
+    }
+
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.dims[0] * (y + self.dims[1] * z)
+    }
+
+    pub fn center(&self, x: usize, y: usize, z: usize) -> Vec3 {
+        self.origin + (glam::vec3(x as f32, y as f32, z as f32) + 0.5) * self.cell
+    }
+
+    pub fn count(&self) -> usize {
+        self.occupied.iter().filter(|o| **o).count()
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.count() as f32 * self.cell.powi(3)
+    }
+
+    /// Marks the cells whose centres lie inside `mesh`, one ray per column along +z
+    pub fn fill(&mut self, mesh: &Mesh) {
+        let bvh = Bvh::new(mesh);
+        let below = self.origin.z - self.cell;
+        for y in 0..self.dims[1] {
+            for x in 0..self.dims[0] {
+                let c = self.center(x, y, 0);
+                let crossings = bvh.crossings(glam::vec3(c.x, c.y, below), Vec3::Z);
+                let mut inside = 0;
+                for z in 0..self.dims[2] {
+                    let t = self.center(x, y, z).z - below;
+                    while inside < crossings.len() && crossings[inside] < t {
+                        inside += 1;
+                    }
+                    let i = self.index(x, y, z);
+                    self.occupied[i] = inside % 2 == 1;
+                }
+            }
+        }
+    }
+}
+
+pub fn voxelize(mesh: &Mesh, bounds: &Aabb, resolution: usize) -> anyhow::Result<VoxelGrid> {
+    check_watertight(mesh, "voxelized")?;
+    let mut grid = VoxelGrid::new(bounds, resolution);
+    grid.fill(mesh);
+    Ok(grid)
+}
+
+fn iou(intersection: usize, union: usize) -> f32 {
+    if union == 0 {
+        return 0.0;
+    }
+    intersection as f32 / union as f32
+}
+
+/// Volumetric intersection over union on a shared voxel grid
+pub fn voxel_iou(target: &Mesh, candidate: &Mesh, resolution: usize) -> anyhow::Result<f32> {
+    check_watertight(target, "target")?;
+    check_watertight(candidate, "candidate")?;
+
+    let bounds =
+        Aabb::from_points(&target.positions).union(&Aabb::from_points(&candidate.positions));
+    let mut a = VoxelGrid::new(&bounds, resolution);
+    let mut b = a.clone();
+    a.fill(target);
+    b.fill(candidate);
+
+    let (mut intersection, mut union) = (0, 0);
+    for (a, b) in a.occupied.iter().zip(&b.occupied) {
+        intersection += (*a && *b) as usize;
+        union += (*a || *b) as usize;
+    }
+    Ok(iou(intersection, union))
+}
+
+/// Volumetric intersection over union from seeded inside/outside queries
+pub fn sampled_iou(target: &Mesh, candidate: &Mesh, samples: usize) -> anyhow::Result<f32> {
+    check_watertight(target, "target")?;
+    check_watertight(candidate, "candidate")?;
+
+    let bounds =
+        Aabb::from_points(&target.positions).union(&Aabb::from_points(&candidate.positions));
+    let (a, b) = (Bvh::new(target), Bvh::new(candidate));
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+
+    let (mut intersection, mut union) = (0, 0);
+    for _ in 0..samples {
+        let t = glam::vec3(rng.random(), rng.random(), rng.random());
+        let p = bounds.min + bounds.size() * t;
+        let (in_a, in_b) = (a.contains(p), b.contains(p));
+        intersection += (in_a && in_b) as usize;
+        union += (in_a || in_b) as usize;
+    }
+    Ok(iou(intersection, union))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::tests::{cuboid, unit_cube};
+
+    #[test]
+    fn watertightness() {
+        assert!(is_watertight(&unit_cube()));
+        let mut open = unit_cube();
+        open.triangles.pop();
+        assert!(!is_watertight(&open));
+        assert!(!is_watertight(&Mesh::default()));
+    }
+
+    #[test]
+    fn voxelized_cube_fills_its_bounds() {
+        let cube = unit_cube();
+        let bounds = Aabb::from_points(&cube.positions);
+        let grid = voxelize(&cube, &bounds, 10).unwrap();
+        assert_eq!(grid.dims, [10, 10, 10]);
+        assert_eq!(grid.count(), 1000);
+        assert!((grid.volume() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn iou_of_a_cube_and_its_double() {
+        let cube = unit_cube();
+        let double = cuboid(Vec3::ZERO, Vec3::new(1.0, 1.0, 2.0));
+        assert_eq!(voxel_iou(&cube, &cube, 16).unwrap(), 1.0);
+        assert!((voxel_iou(&cube, &double, 32).unwrap() - 0.5).abs() < 1e-6);
+        assert!((sampled_iou(&cube, &double, IOU_SAMPLES).unwrap() - 0.5).abs() < 0.03);
+    }
+
+    #[test]
+    fn open_meshes_are_rejected() {
+        let mut open = unit_cube();
+        open.triangles.pop();
+        assert!(voxel_iou(&unit_cube(), &open, 8).is_err());
+        assert!(sampled_iou(&open, &unit_cube(), 8).is_err());
+    }
+}